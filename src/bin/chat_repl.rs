@@ -0,0 +1,489 @@
+//! Interactive chat client running on a local simulated network.
+//!
+//! The network is made of this client, a drone, a chat server and an echo client,
+//! which registers to the chat server and sends back every message it receives:
+//! ```text
+//! client 1 ─┐
+//!           ├─ drone 10 ── chat server 20
+//! echo 2 ───┘
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, Write};
+use std::thread;
+use std::time::Duration;
+use ap_client::{
+    ChatState, ClientConfig, ClientEvent, Command, DeliveryStatus, DibClient, DiscardSink,
+    DiscoveryConfig, NodeCommand, NodeLogic, NodeRuntime, Scenario, ServerDirectory,
+};
+use crossbeam_channel::{select_biased, unbounded, Receiver, Sender};
+use messages::node_event::NodeEvent;
+use messages::{ChatRequest, ChatResponse, ErrorType, Message, MessageType, RequestType, ResponseType, ServerType};
+use wg_2024::network::{NodeId, SourceRoutingHeader};
+use wg_2024::packet::{FloodResponse, NodeType, Packet, PacketType};
+
+const CLIENT_ID: NodeId = 1;
+const ECHO_ID: NodeId = 2;
+const DRONE_ID: NodeId = 10;
+const CHAT_SERVER_ID: NodeId = 20;
+
+const HELP: &str = "\
+Commands:
+  /servers                        list the chat servers
+  /register <server>              register to a chat server
+  /who <server>                   ask a chat server for its registered clients
+  /msg <server> <client> <text>   send a message to a client through a chat server
+  /inbox                          show the received and sent messages
+  /help                           show this help
+  /quit                           exit";
+
+fn main() {
+    let (node_event_tx, node_event_rx) = unbounded::<NodeEvent>();
+    // Nobody controls the simulation, the events are just dropped
+    thread::spawn(move || for _ in node_event_rx {});
+
+    let mut packet_tx = HashMap::new();
+    let mut packet_rx = HashMap::new();
+    for node_id in [CLIENT_ID, ECHO_ID, DRONE_ID, CHAT_SERVER_ID] {
+        let (tx, rx) = unbounded::<Packet>();
+        packet_tx.insert(node_id, tx);
+        packet_rx.insert(node_id, rx);
+    }
+    let neighbours = |nodes: &[NodeId]| -> HashMap<NodeId, Sender<Packet>> {
+        nodes
+            .iter()
+            .map(|node_id| (*node_id, packet_tx[node_id].clone()))
+            .collect()
+    };
+    let mut receiver = |node_id: NodeId| packet_rx.remove(&node_id).expect("Each node has a receiver");
+
+    let drone = RelayDrone::new(
+        DRONE_ID,
+        receiver(DRONE_ID),
+        neighbours(&[CLIENT_ID, ECHO_ID, CHAT_SERVER_ID]),
+    );
+    thread::spawn(move || drone.run());
+
+    let chat_server_quit_tx = spawn_chat_server(
+        CHAT_SERVER_ID,
+        receiver(CHAT_SERVER_ID),
+        neighbours(&[DRONE_ID]),
+        &node_event_tx,
+    );
+
+    let echo_scenario = format!(
+        r#"{{
+            "destinations": {{ "chat": {CHAT_SERVER_ID} }},
+            "steps": [
+                {{ "to": "chat", "request": {{ "ChatRequest": "Register" }} }},
+                {{ "loop": {{ "steps": [
+                    {{ "wait_for": {{ "kind": "MessageFrom", "from": "chat" }}, "save_as": "msg" }},
+                    {{ "to": "chat", "request": {{ "ChatRequest": {{ "SendMessage": {{
+                        "from": {ECHO_ID}, "to": "${{msg.from}}", "message": "echo: ${{msg}}" }} }} }} }}
+                ] }} }}
+            ]
+        }}"#
+    );
+    let echo_scenario = Scenario::from_json(&echo_scenario).expect("The echo scenario is valid");
+    let (mut echo, echo_command_tx) = DibClient::new_dib_client_with_config(
+        ECHO_ID,
+        receiver(ECHO_ID),
+        neighbours(&[DRONE_ID]),
+        node_event_tx.clone(),
+        unbounded().1,
+        echo_scenario,
+        ClientConfig {
+            media_sink: Box::new(DiscardSink),
+            ..ClientConfig::default()
+        },
+    )
+    .expect("The echo client is valid");
+    thread::spawn(move || echo.run());
+
+    let (event_tx, event_rx) = unbounded();
+    let chat_state = ChatState::new();
+    let server_directory = ServerDirectory::new();
+    let (mut client, command_tx) = DibClient::new_dib_client_with_config(
+        CLIENT_ID,
+        receiver(CLIENT_ID),
+        neighbours(&[DRONE_ID]),
+        node_event_tx,
+        unbounded().1,
+        Scenario::from_actions(Vec::new(), Duration::ZERO),
+        ClientConfig {
+            event_tx: Some(event_tx),
+            media_sink: Box::new(DiscardSink),
+            chat_state: chat_state.clone(),
+            discovery: Some(DiscoveryConfig {
+                candidates: vec![CHAT_SERVER_ID],
+                interval: Some(Duration::from_secs(30)),
+            }),
+            server_directory: server_directory.clone(),
+            ..ClientConfig::default()
+        },
+    )
+    .expect("The client is valid");
+    let client_handle = thread::spawn(move || client.run());
+    thread::spawn(move || print_events(&event_rx));
+
+    println!("Client {CLIENT_ID} connected to chat server {CHAT_SERVER_ID}, client {ECHO_ID} echoes every message");
+    println!("{HELP}");
+    repl(&command_tx, &chat_state, &server_directory);
+
+    let _ = command_tx.send(Command::Quit);
+    let _ = echo_command_tx.send(Command::Quit);
+    let _ = chat_server_quit_tx.send(());
+    if let Ok(Err(error)) = client_handle.join() {
+        eprintln!("Client {CLIENT_ID} stopped with an error: {error}");
+    }
+}
+
+/// Reads the commands from stdin until `/quit` or the end of the input
+fn repl(command_tx: &Sender<Command>, chat_state: &ChatState, server_directory: &ServerDirectory) {
+    let send = |destination: NodeId, request: ChatRequest| {
+        let command = Command::SendRequest {
+            destination,
+            request: RequestType::ChatRequest(request),
+        };
+        if command_tx.send(command).is_err() {
+            println!("The client is not running anymore");
+        }
+    };
+
+    prompt();
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        let mut words = line.split_whitespace();
+
+        match (words.next(), words.next().map(str::parse::<NodeId>)) {
+            (None, _) => {}
+            (Some("/quit"), _) => break,
+            (Some("/help"), _) => println!("{HELP}"),
+            (Some("/servers"), _) => print_servers(chat_state, server_directory),
+            (Some("/inbox"), _) => print_inbox(chat_state),
+            (Some("/register"), Some(Ok(server))) => send(server, ChatRequest::Register),
+            (Some("/who"), Some(Ok(server))) => send(server, ChatRequest::ClientList),
+            (Some("/msg"), Some(Ok(server))) => {
+                let to = words.next().map(str::parse::<NodeId>);
+                let message = words.collect::<Vec<_>>().join(" ");
+                match to {
+                    Some(Ok(to)) if !message.is_empty() => send(
+                        server,
+                        ChatRequest::SendMessage {
+                            from: CLIENT_ID,
+                            to,
+                            message,
+                        },
+                    ),
+                    _ => println!("Usage: /msg <server> <client> <text>"),
+                }
+            }
+            (Some("/register" | "/who" | "/msg"), _) => println!("The server must be a node ID"),
+            (Some(command), _) => println!("Unknown command '{command}', type /help for the list of commands"),
+        }
+        prompt();
+    }
+}
+
+fn prompt() {
+    print!("> ");
+    let _ = std::io::stdout().flush();
+}
+
+fn print_servers(chat_state: &ChatState, server_directory: &ServerDirectory) {
+    let mut servers = server_directory.servers_of_type(&ServerType::Chat);
+    servers.extend(chat_state.servers());
+    servers.sort_unstable();
+    servers.dedup();
+    if servers.is_empty() {
+        println!("  No chat server discovered yet");
+    }
+
+    for server in servers {
+        let registered = if chat_state.is_registered(server) {
+            "registered"
+        } else {
+            "not registered"
+        };
+        println!("  {server}: {registered}, known clients {:?}", chat_state.clients(server));
+    }
+}
+
+fn print_inbox(chat_state: &ChatState) {
+    for server in chat_state.servers() {
+        println!("Server {server}:");
+        for message in chat_state.inbox(server) {
+            println!("  from {}: {}", message.from, message.message);
+        }
+        for message in chat_state.outbox(server) {
+            let status = match message.status {
+                DeliveryStatus::Queued => "queued",
+                DeliveryStatus::Sent => "sent",
+                DeliveryStatus::Acknowledged => "delivered",
+                DeliveryStatus::Failed => "failed",
+            };
+            println!("  to {} ({status}): {}", message.to, message.message);
+        }
+    }
+}
+
+/// Prints the pushed messages and the answers to the commands as soon as they arrive
+fn print_events(event_rx: &Receiver<ClientEvent>) {
+    for event in event_rx {
+        match event {
+            ClientEvent::MessageReceived {
+                server,
+                from,
+                message,
+            } => println!("\n[{server}] {from}: {message}"),
+            ClientEvent::ClientListReceived { server, clients } => {
+                println!("\n[{server}] registered clients: {clients:?}");
+            }
+            ClientEvent::RequestFailed(failure) => {
+                println!("\nRequest {:?} to {} failed", failure.request, failure.destination);
+            }
+            ClientEvent::Error(error) => println!("\nError: {error}"),
+            _ => continue,
+        }
+        prompt();
+    }
+}
+
+/// A drone that forwards every packet along its route, without dropping any
+struct RelayDrone {
+    id: NodeId,
+    packet_rx: Receiver<Packet>,
+    neighbours: HashMap<NodeId, Sender<Packet>>,
+    seen_floods: HashSet<(NodeId, u64)>,
+}
+
+impl RelayDrone {
+    fn new(id: NodeId, packet_rx: Receiver<Packet>, neighbours: HashMap<NodeId, Sender<Packet>>) -> Self {
+        Self {
+            id,
+            packet_rx,
+            neighbours,
+            seen_floods: HashSet::new(),
+        }
+    }
+
+    fn run(mut self) {
+        while let Ok(packet) = self.packet_rx.recv() {
+            self.handle_packet(packet);
+        }
+    }
+
+    fn handle_packet(&mut self, mut packet: Packet) {
+        let PacketType::FloodRequest(request) = &mut packet.pack_type else {
+            packet.routing_header.hop_index += 1;
+            match packet.routing_header.hops.get(packet.routing_header.hop_index) {
+                Some(next_hop) => self.send(*next_hop, packet),
+                None => log::warn!("Drone {}: packet at the end of its route dropped", self.id),
+            }
+            return;
+        };
+
+        let previous_hop = request
+            .path_trace
+            .last()
+            .map_or(request.initiator_id, |(node_id, _)| *node_id);
+        request.path_trace.push((self.id, NodeType::Drone));
+
+        let targets: Vec<NodeId> = self
+            .neighbours
+            .keys()
+            .copied()
+            .filter(|node_id| *node_id != previous_hop)
+            .collect();
+
+        if self.seen_floods.insert((request.initiator_id, request.flood_id)) && !targets.is_empty() {
+            for target in targets {
+                self.send(target, packet.clone());
+            }
+            return;
+        }
+
+        let mut hops: Vec<NodeId> = request.path_trace.iter().rev().map(|(node_id, _)| *node_id).collect();
+        if hops.last() != Some(&request.initiator_id) {
+            hops.push(request.initiator_id);
+        }
+        let response = Packet {
+            routing_header: SourceRoutingHeader { hop_index: 1, hops },
+            session_id: packet.session_id,
+            pack_type: PacketType::FloodResponse(FloodResponse {
+                flood_id: request.flood_id,
+                path_trace: request.path_trace.clone(),
+            }),
+        };
+        self.send(previous_hop, response);
+    }
+
+    fn send(&self, node_id: NodeId, packet: Packet) {
+        match self.neighbours.get(&node_id) {
+            Some(tx) => {
+                let _ = tx.send(packet);
+            }
+            None => log::warn!("Drone {}: {node_id} is not a neighbour", self.id),
+        }
+    }
+}
+
+/// Starts a chat server node, run by the same `NodeRuntime` as the clients.
+/// Returns the sender stopping it
+fn spawn_chat_server(
+    node_id: NodeId,
+    packet_rx: Receiver<Packet>,
+    neighbours: HashMap<NodeId, Sender<Packet>>,
+    node_event_tx: &Sender<NodeEvent>,
+) -> Sender<()> {
+    let (drone_command_tx, drone_command_rx) = unbounded();
+    let mut runtime = NodeRuntime::new(
+        node_id,
+        NodeType::Server,
+        packet_rx,
+        neighbours,
+        node_event_tx.clone(),
+        drone_command_rx,
+        |channels| ChatServer {
+            node_id: channels.node_id,
+            registered: HashSet::new(),
+            next_session_id: 0,
+            transmitter_tx: channels.transmitter_tx,
+            listener_rx: channels.listener_rx,
+            command_rx: channels.command_rx,
+        },
+    )
+    .expect("The chat server is valid");
+
+    let (quit_tx, quit_rx) = unbounded::<()>();
+    thread::spawn(move || {
+        // The command sender must outlive the node, or the transmitter would report a disconnection
+        let _drone_command_tx = drone_command_tx;
+        if let Err(error) = runtime.start() {
+            eprintln!("Chat server {node_id} cannot start: {error}");
+            return;
+        }
+        if let Err(error) = runtime.next_command(&quit_rx) {
+            eprintln!("Chat server {node_id}: {error}");
+        }
+        if let Err(error) = runtime.stop() {
+            eprintln!("Chat server {node_id} stopped with an error: {error}");
+        }
+    });
+    quit_tx
+}
+
+#[derive(Debug)]
+enum ServerCommand {
+    Quit,
+}
+
+impl NodeCommand for ServerCommand {
+    fn quit() -> Self {
+        ServerCommand::Quit
+    }
+}
+
+/// The chat server cannot reach its transmitter anymore
+#[derive(Debug)]
+struct TransmitterDisconnected;
+
+impl Display for TransmitterDisconnected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the transmitter is disconnected")
+    }
+}
+
+impl std::error::Error for TransmitterDisconnected {}
+
+/// Minimal chat server: it forwards the messages between its registered clients
+struct ChatServer {
+    node_id: NodeId,
+    registered: HashSet<NodeId>,
+    next_session_id: u64,
+    transmitter_tx: Sender<Message>,
+    listener_rx: Receiver<Message>,
+    command_rx: Receiver<ServerCommand>,
+}
+
+impl NodeLogic for ChatServer {
+    type Command = ServerCommand;
+    type Error = TransmitterDisconnected;
+
+    fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    fn run_logic(&mut self) -> Result<(), TransmitterDisconnected> {
+        let command_rx = self.command_rx.clone();
+        let listener_rx = self.listener_rx.clone();
+        loop {
+            select_biased! {
+                recv(command_rx) -> command => match command {
+                    Ok(ServerCommand::Quit) | Err(_) => return Ok(()),
+                },
+                recv(listener_rx) -> message => match message {
+                    Ok(message) => self.process_message(message)?,
+                    // The listener stopped, the runtime reports it
+                    Err(_) => return Ok(()),
+                },
+            }
+        }
+    }
+}
+
+impl ChatServer {
+    fn process_message(&mut self, message: Message) -> Result<(), TransmitterDisconnected> {
+        let MessageType::Request(request) = message.content else {
+            return Ok(());
+        };
+        let source = message.source;
+        let session_id = message.session_id;
+
+        let response = match &request {
+            RequestType::ChatRequest(ChatRequest::Register) => {
+                self.registered.insert(source);
+                return Ok(());
+            }
+            RequestType::ChatRequest(ChatRequest::ClientList) => {
+                let mut clients: Vec<NodeId> = self.registered.iter().copied().collect();
+                clients.sort_unstable();
+                MessageType::Response(ResponseType::ChatResponse(ChatResponse::ClientList(clients)))
+            }
+            RequestType::ChatRequest(ChatRequest::SendMessage { to, message, .. })
+                if self.registered.contains(&source) && self.registered.contains(to) =>
+            {
+                let push = MessageType::Response(ResponseType::ChatResponse(ChatResponse::MessageFrom {
+                    from: source,
+                    message: message.clone(),
+                }));
+                let push_session_id = self.new_session_id();
+                self.send(push_session_id, *to, push)?;
+                MessageType::Response(ResponseType::ChatResponse(ChatResponse::MessageSent))
+            }
+            RequestType::DiscoveryRequest(()) => {
+                MessageType::Response(ResponseType::DiscoveryResponse(ServerType::Chat))
+            }
+            _ => MessageType::Error(ErrorType::Unsupported(request.clone())),
+        };
+        self.send(session_id, source, response)
+    }
+
+    fn new_session_id(&mut self) -> u64 {
+        self.next_session_id += 1;
+        (u64::from(self.node_id) << 56) | self.next_session_id
+    }
+
+    fn send(&self, session_id: u64, destination: NodeId, content: MessageType) -> Result<(), TransmitterDisconnected> {
+        let message = Message {
+            source: self.node_id,
+            destination,
+            session_id,
+            content,
+        };
+        self.transmitter_tx.send(message).map_err(|_| TransmitterDisconnected)
+    }
+}
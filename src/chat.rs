@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime};
+use wg_2024::network::NodeId;
+
+/// A message received from another client through a chat server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    pub from: NodeId,
+    pub message: String,
+    pub received_at: SystemTime,
+}
+
+/// Where a sent message is in its delivery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// The message has been accepted by the client, but not handed to the network yet
+    Queued,
+    /// The message has been sent to the server, which did not confirm it yet.
+    /// It is sent again following the `RetryPolicy` of the client until it is confirmed
+    Sent,
+    /// The server confirmed the message with `MessageSent`
+    Acknowledged,
+    /// The server never confirmed the message, or rejected it
+    Failed,
+}
+
+/// A message sent to another client through a chat server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    /// Session of the first attempt, with which `MessageSent` is matched
+    pub session_id: u64,
+    pub to: NodeId,
+    pub message: String,
+    pub status: DeliveryStatus,
+    /// Number of times the message has been sent
+    pub attempts: u32,
+    pub queued_at: SystemTime,
+    /// When the first attempt has been sent
+    pub sent_at: Option<SystemTime>,
+    pub acknowledged_at: Option<SystemTime>,
+}
+
+impl OutgoingMessage {
+    /// Returns the time between the first attempt and the acknowledgement
+    #[must_use]
+    pub fn latency(&self) -> Option<Duration> {
+        let sent_at = self.sent_at?;
+        self.acknowledged_at?.duration_since(sent_at).ok()
+    }
+}
+
+/// Delivery outcome of the messages sent through a chat server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeliveryStats {
+    pub queued: usize,
+    pub sent: usize,
+    pub acknowledged: usize,
+    pub failed: usize,
+    /// Number of attempts sent after the first ones
+    pub resent: u32,
+    pub average_latency: Option<Duration>,
+    pub max_latency: Option<Duration>,
+}
+
+impl DeliveryStats {
+    fn of<'a, I: Iterator<Item = &'a OutgoingMessage>>(outbox: I) -> Self {
+        let mut stats = Self::default();
+        let mut total_latency = Duration::ZERO;
+        let mut measured = 0;
+
+        for message in outbox {
+            match message.status {
+                DeliveryStatus::Queued => stats.queued += 1,
+                DeliveryStatus::Sent => stats.sent += 1,
+                DeliveryStatus::Acknowledged => stats.acknowledged += 1,
+                DeliveryStatus::Failed => stats.failed += 1,
+            }
+            stats.resent += message.attempts.saturating_sub(1);
+
+            if let Some(latency) = message.latency() {
+                total_latency += latency;
+                measured += 1;
+                stats.max_latency = stats.max_latency.max(Some(latency));
+            }
+        }
+
+        if measured > 0 {
+            stats.average_latency = Some(total_latency / measured);
+        }
+        stats
+    }
+}
+
+/// An entry of the conversation with a client, see `ChatState::conversation`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversationEntry {
+    Received(ChatMessage),
+    Sent(OutgoingMessage),
+}
+
+impl ConversationEntry {
+    #[must_use]
+    pub fn time(&self) -> SystemTime {
+        match self {
+            ConversationEntry::Received(message) => message.received_at,
+            ConversationEntry::Sent(message) => message.queued_at,
+        }
+    }
+}
+
+/// What the client knows about a chat server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChatServerState {
+    /// Whether `ChatRequest::Register` has been sent to the server
+    pub registered: bool,
+    /// The latest client list received from the server
+    pub clients: Vec<NodeId>,
+    /// When `clients` has been received, `None` if no client list has been received yet
+    pub clients_updated_at: Option<SystemTime>,
+    /// The received messages, oldest first
+    pub inbox: Vec<ChatMessage>,
+    /// The sent messages, oldest first
+    pub outbox: Vec<OutgoingMessage>,
+}
+
+/// Chat state of the client for every chat server, shared between the client and the embedding application.
+/// Cloning it gives another handle to the same state
+#[derive(Debug, Clone, Default)]
+pub struct ChatState {
+    servers: Arc<RwLock<HashMap<NodeId, ChatServerState>>>,
+}
+
+impl ChatState {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the chat servers the client interacted with, in ascending order
+    #[must_use]
+    pub fn servers(&self) -> Vec<NodeId> {
+        let mut servers: Vec<NodeId> = self.read().keys().copied().collect();
+        servers.sort_unstable();
+        servers
+    }
+
+    /// Returns a snapshot of the state of `server`
+    #[must_use]
+    pub fn server(&self, server: NodeId) -> Option<ChatServerState> {
+        self.read().get(&server).cloned()
+    }
+
+    #[must_use]
+    pub fn is_registered(&self, server: NodeId) -> bool {
+        self.read().get(&server).is_some_and(|state| state.registered)
+    }
+
+    /// Returns the latest client list received from `server`
+    #[must_use]
+    pub fn clients(&self, server: NodeId) -> Vec<NodeId> {
+        self.read()
+            .get(&server)
+            .map(|state| state.clients.clone())
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn inbox(&self, server: NodeId) -> Vec<ChatMessage> {
+        self.read()
+            .get(&server)
+            .map(|state| state.inbox.clone())
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn outbox(&self, server: NodeId) -> Vec<OutgoingMessage> {
+        self.read()
+            .get(&server)
+            .map(|state| state.outbox.clone())
+            .unwrap_or_default()
+    }
+
+    /// Returns the delivery outcome of the messages sent through `server`
+    #[must_use]
+    pub fn delivery_stats(&self, server: NodeId) -> DeliveryStats {
+        self.read()
+            .get(&server)
+            .map(|state| DeliveryStats::of(state.outbox.iter()))
+            .unwrap_or_default()
+    }
+
+    /// Returns the delivery outcome of the messages sent through every chat server
+    #[must_use]
+    pub fn total_delivery_stats(&self) -> DeliveryStats {
+        DeliveryStats::of(self.read().values().flat_map(|state| state.outbox.iter()))
+    }
+
+    /// Returns the messages exchanged with `peer` through `server`, oldest first
+    #[must_use]
+    pub fn conversation(&self, server: NodeId, peer: NodeId) -> Vec<ConversationEntry> {
+        let servers = self.read();
+        let Some(state) = servers.get(&server) else {
+            return Vec::new();
+        };
+
+        let received = state
+            .inbox
+            .iter()
+            .filter(|message| message.from == peer)
+            .cloned()
+            .map(ConversationEntry::Received);
+        let sent = state
+            .outbox
+            .iter()
+            .filter(|message| message.to == peer)
+            .cloned()
+            .map(ConversationEntry::Sent);
+
+        let mut conversation: Vec<ConversationEntry> = received.chain(sent).collect();
+        conversation.sort_by_key(ConversationEntry::time);
+        conversation
+    }
+
+    pub(crate) fn set_registered(&self, server: NodeId) {
+        self.write().entry(server).or_default().registered = true;
+    }
+
+    pub(crate) fn set_unregistered(&self, server: NodeId) {
+        if let Some(state) = self.write().get_mut(&server) {
+            state.registered = false;
+        }
+    }
+
+    pub(crate) fn set_clients(&self, server: NodeId, clients: Vec<NodeId>) {
+        let mut servers = self.write();
+        let state = servers.entry(server).or_default();
+        state.clients = clients;
+        state.clients_updated_at = Some(SystemTime::now());
+    }
+
+    pub(crate) fn receive(&self, server: NodeId, from: NodeId, message: String) {
+        self.write().entry(server).or_default().inbox.push(ChatMessage {
+            from,
+            message,
+            received_at: SystemTime::now(),
+        });
+    }
+
+    /// Adds the message that is going to be sent with `session_id` to the outbox of `server`
+    pub(crate) fn queue(&self, server: NodeId, session_id: u64, to: NodeId, message: String) {
+        self.write().entry(server).or_default().outbox.push(OutgoingMessage {
+            session_id,
+            to,
+            message,
+            status: DeliveryStatus::Queued,
+            attempts: 0,
+            queued_at: SystemTime::now(),
+            sent_at: None,
+            acknowledged_at: None,
+        });
+    }
+
+    /// Records that an attempt of the message first sent with `session_id` has been sent
+    pub(crate) fn mark_sent(&self, server: NodeId, session_id: u64) {
+        self.update(server, session_id, |outgoing| {
+            if outgoing.status == DeliveryStatus::Queued {
+                outgoing.status = DeliveryStatus::Sent;
+                outgoing.sent_at = Some(SystemTime::now());
+            }
+            outgoing.attempts += 1;
+        });
+    }
+
+    pub(crate) fn acknowledge(&self, server: NodeId, session_id: u64) {
+        self.update(server, session_id, |outgoing| {
+            outgoing.status = DeliveryStatus::Acknowledged;
+            outgoing.acknowledged_at = Some(SystemTime::now());
+        });
+    }
+
+    pub(crate) fn fail(&self, server: NodeId, session_id: u64) {
+        self.update(server, session_id, |outgoing| {
+            outgoing.status = DeliveryStatus::Failed;
+        });
+    }
+
+    fn update<F: FnOnce(&mut OutgoingMessage)>(&self, server: NodeId, session_id: u64, update: F) {
+        let mut servers = self.write();
+        let outgoing = servers.get_mut(&server).and_then(|state| {
+            state
+                .outbox
+                .iter_mut()
+                .find(|outgoing| outgoing.session_id == session_id)
+        });
+        match outgoing {
+            Some(outgoing) => update(outgoing),
+            None => log::warn!("Session {session_id}: no message sent to {server} with this session"),
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<NodeId, ChatServerState>> {
+        self.servers.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<NodeId, ChatServerState>> {
+        self.servers.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
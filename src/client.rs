@@ -1,19 +1,61 @@
-use std::thread;
-use std::time::Duration;
-use crossbeam_channel::{select_biased, Receiver, Sender};
-use messages::{ChatRequest, ChatResponse, MediaRequest, MediaResponse, Message, MessageType, RequestType, ResponseType, ServerType, TextResponse};
-use rand::{Rng, RngCore};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crossbeam_channel::{never, select_biased, Receiver, Sender};
+use messages::{ChatRequest, ChatResponse, ErrorType, MediaRequest, MediaResponse, Message, MessageType, RequestType, ResponseType, ServerType, TextRequest, TextResponse};
+use rand::RngCore;
 use wg_2024::network::NodeId;
-use regex::Regex;
 use crate::logic::{ClientCommand, ClientLogic, Getter};
-
+use crate::chat::ChatState;
+use crate::config::{ClientConfig, PipelineLimits, RetryPolicy};
+use crate::crawl::Crawler;
+use crate::directory::{DiscoverySweep, ServerDirectory};
+use crate::document::{DocumentAssembler, PendingDocument};
+use crate::error::ClientError;
+use crate::event::{ClientEvent, RequestFailure};
+use crate::markup::{parse_references, Reference, ReferenceKind};
+use crate::media::{quarantine, validate_media, MediaError, MediaLimits};
+use crate::media_cache::{MediaCache, MediaKey, MediaLookup};
+use crate::media_fallback::{MediaFallbackPolicy, MediaFallbacks};
+use crate::media_sink::MediaSink;
+use crate::report::ScenarioReport;
+use crate::routing::Router;
+use crate::runner::ScenarioRunner;
+use crate::scenario::Scenario;
+use crate::session::{PendingRequest, PendingState, SessionMatch, SessionTable};
+use crate::state::ClientState;
+
+/// Maximum time spent waiting for an event when there is nothing scheduled
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The default `ClientLogic`: runs a `Scenario` against text, media and chat servers
 pub struct Client {
     node_id: NodeId,
     client_logic_to_transmitter_tx: Sender<Message>,
     listener_to_client_logic_rx: Receiver<Message>,
     command_rx: Receiver<ClientCommand>,
-    actions: Vec<(NodeId, RequestType)>,
-    sleep_time: Duration,
+    /// `false` once the listener has been found disconnected
+    listener_connected: bool,
+    /// `true` once `run` has been called: a restarted logic resumes where it crashed
+    started: bool,
+    runner: ScenarioRunner,
+    sessions: SessionTable,
+    retry_policy: RetryPolicy,
+    pipeline_limits: PipelineLimits,
+    event_tx: Option<Sender<ClientEvent>>,
+    report_path: Option<PathBuf>,
+    media_sink: Box<dyn MediaSink>,
+    media_limits: MediaLimits,
+    quarantine_dir: Option<PathBuf>,
+    media_cache: MediaCache,
+    media_fallback: MediaFallbackPolicy,
+    media_fallbacks: MediaFallbacks,
+    documents: Option<DocumentAssembler>,
+    crawler: Option<Crawler>,
+    chat_state: ChatState,
+    server_directory: ServerDirectory,
+    discovery: Option<DiscoverySweep>,
+    router: Router,
 }
 
 impl Getter for Client {
@@ -35,103 +77,124 @@ impl Getter for Client {
 }
 
 impl ClientLogic for Client {
-    fn run(&mut self) {
-        let mut rng = rand::rng();
-        let actions = self.actions.clone();
-        for (destination, action) in actions {
-            let expect_response = match &action {
-                RequestType::ChatRequest(chat_request) => {
-                    !matches!(chat_request, ChatRequest::Register)
-                }
-                _ => true
-            };
-
-            match &action {
-                RequestType::TextRequest(_) => {}
-                RequestType::MediaRequest(_) => {}
-                RequestType::ChatRequest(chat_request) => {
+    fn run(&mut self) -> Result<(), ClientError> {
+        if !self.started {
+            self.started = true;
+            self.start_crawl();
+        }
 
-                }
-                RequestType::DiscoveryRequest(_) => {}
-            }
-            let message = Message {
-                source: self.node_id,
-                destination,
-                session_id: rng.next_u64(),
-                content: MessageType::Request(
-                    action
-                ),
-            };
-
-            self
-                .client_logic_to_transmitter_tx
-                .send(message)
-                .unwrap_or_else(|_| panic!("Cannot send messages to transmitter"));
-
-
-            if expect_response {
-                select_biased! {
-                    recv(self.get_server_command_rx()) -> command => {
-                        if let Ok(command) = command {
-                            match command {
-                                ClientCommand::Quit => {
-                                    break;
-                                },
-                            }
-                        }
-                        panic!("Error while receiving ServerLogicCommand");
-                    },
-                    recv(self.get_listener_to_server_logic_rx()) -> message => {
-                        if let Ok(message) = message {
-                            self.process_message(&message);
-                        } else {
-                            panic!("Error while receiving a message from listener");
-                        }
-                    },
-                }
+        let result = loop {
+            self.sweep_discovery();
+            self.dispatch_steps();
+            if let Some(report) = self.runner.take_report(false) {
+                self.publish_report(report);
             }
+            self.publish_finished_crawls();
+            match self.handle_next_event() {
+                Ok(true) => {}
+                Ok(false) => break Ok(()),
+                Err(error) => break Err(error),
+            }
+        };
 
-            thread::sleep(self.sleep_time);
+        if let Some(report) = self.runner.take_report(true) {
+            self.publish_report(report);
         }
+        result
+    }
 
-        loop {
-            select_biased! {
-                recv(self.get_server_command_rx()) -> command => {
-                    if let Ok(command) = command {
-                        match command {
-                            ClientCommand::Quit => {
-                                break;
-                            },
-                        }
-                    }
-                    panic!("Error while receiving ServerLogicCommand");
-                },
-                recv(self.get_listener_to_server_logic_rx()) -> message => {
-                    if let Ok(message) = message {
-                        self.process_message(&message);
-                    } else {
-                        panic!("Error while receiving a message from listener");
-                    }
-                },
+    fn process_response(&mut self, session_id: u64, source_id: NodeId, response_type: &ResponseType) {
+        let matched = match self.sessions.resolve(session_id, source_id) {
+            SessionMatch::Matched { session_id: current_session_id, pending } => {
+                if current_session_id != session_id {
+                    log::info!(
+                        "Session {session_id}: late response, request already sent again with session {current_session_id}"
+                    );
+                }
+                log::info!(
+                    "Session {session_id}: response from {source_id} to {:?} after {:?} (attempt {})",
+                    pending.request,
+                    pending.attempt_sent_at.elapsed(),
+                    pending.attempt
+                );
+                Some((pending.origin_session(current_session_id), pending))
             }
-        }
-    }
+            SessionMatch::WrongSource { expected } => {
+                log::warn!(
+                    "Session {session_id}: response expected from {expected}, but received from {source_id}"
+                );
+                None
+            }
+            SessionMatch::Duplicate => {
+                log::warn!(
+                    "Session {session_id}: duplicated response from {source_id} ignored: {response_type:?}"
+                );
+                return;
+            }
+            SessionMatch::Unknown if Self::is_push(response_type) => {
+                log::info!("Session {session_id}: unsolicited response from {source_id}");
+                None
+            }
+            SessionMatch::Unknown => {
+                log::warn!("Session {session_id}: response from {source_id} does not match any request");
+                None
+            }
+        };
 
+        let origin_session = matched.as_ref().map(|(origin_session, _)| *origin_session);
+        let request = matched.as_ref().map(|(_, pending)| &pending.request);
+        let latency = matched
+            .as_ref()
+            .map(|(_, pending)| pending.attempt_sent_at.elapsed());
+        self.runner
+            .on_response(origin_session, source_id, response_type);
 
-    fn process_response(&mut self, _session_id: u64, source_id: NodeId, response_type: &ResponseType) {
         match response_type {
             ResponseType::TextResponse(text_response) => {
-                self.process_text_response(source_id, text_response);
+                self.process_text_response(source_id, request, text_response);
             }
             ResponseType::MediaResponse(media_response) => {
-                self.process_media_response(source_id, media_response);
+                self.process_media_response(source_id, request, media_response);
             }
             ResponseType::ChatResponse(chat_response) => {
-                self.process_chat_response(source_id, chat_response);
+                self.process_chat_response(source_id, origin_session, chat_response);
             }
             ResponseType::DiscoveryResponse(server_type) => {
-                self.process_discovery_response(source_id, server_type);
+                self.process_discovery_response(source_id, server_type, latency);
+            }
+        }
+    }
+
+    fn process_error(&mut self, session_id: u64, source_id: NodeId, error_type: &ErrorType) {
+        if let SessionMatch::Matched { session_id: current_session_id, mut pending } =
+            self.sessions.resolve(session_id, source_id)
+        {
+            log::warn!(
+                "Session {session_id}: request {:?} to {source_id} failed with error {error_type:?}",
+                pending.request
+            );
+
+            // The server may have forgotten the registration: register again and send the message once more
+            if Self::is_chat_message(&pending.request) && !pending.reregistered {
+                log::info!("Registering again to {source_id} to deliver the message of session {session_id}");
+                self.chat_state.set_unregistered(source_id);
+                self.send_request(source_id, RequestType::ChatRequest(ChatRequest::Register));
+                pending.reregistered = true;
+                self.retransmit_request(current_session_id, pending);
+                return;
             }
+
+            self.runner
+                .on_error(pending.origin_session(current_session_id), source_id, error_type);
+            self.request_ended_without_response(
+                pending.origin_session(current_session_id),
+                pending.destination,
+                &pending.request,
+            );
+        } else {
+            log::warn!(
+                "From node {source_id} with session_id {session_id}, received error {error_type:?}"
+            );
         }
     }
 }
@@ -142,152 +205,692 @@ impl Client {
         client_logic_to_transmitter_tx: Sender<Message>,
         listener_to_client_logic_rx: Receiver<Message>,
         command_rx: Receiver<ClientCommand>,
-        actions: Vec<(NodeId, RequestType)>,
-        sleep_time: Duration,
+        scenario: Scenario,
+        config: ClientConfig,
     ) -> Self {
         Self {
             node_id,
             client_logic_to_transmitter_tx,
             listener_to_client_logic_rx,
             command_rx,
-            actions,
-            sleep_time
+            listener_connected: true,
+            started: false,
+            runner: ScenarioRunner::new(scenario),
+            sessions: SessionTable::default(),
+            retry_policy: config.retry_policy,
+            pipeline_limits: config.pipeline_limits,
+            event_tx: config.event_tx,
+            report_path: config.report_path,
+            media_sink: config.media_sink,
+            media_limits: config.media_limits,
+            quarantine_dir: config.quarantine_dir,
+            media_cache: MediaCache::new(config.media_cache_budget),
+            media_fallback: config.media_fallback,
+            media_fallbacks: MediaFallbacks::default(),
+            documents: config.documents.map(DocumentAssembler::new),
+            crawler: config.crawl.map(Crawler::new),
+            chat_state: config.chat_state,
+            router: Router::new(
+                config.server_directory.clone(),
+                config.routing_strategy,
+                config.discovery.is_some(),
+            ),
+            server_directory: config.server_directory,
+            discovery: config.discovery.map(DiscoverySweep::new),
+        }
+    }
+
+    /// Sends the requests of the scenario that are ready to be dispatched
+    fn dispatch_steps(&mut self) {
+        while let Some(dispatch) =
+            self.runner
+                .next_dispatch(&self.sessions, &self.pipeline_limits, &mut self.router)
+        {
+            let session_id = self.send_request(dispatch.destination, dispatch.request);
+            let awaiting_response = self.sessions.is_pending(session_id);
+            self.runner.dispatched(
+                dispatch.instruction,
+                dispatch.destination,
+                session_id,
+                awaiting_response,
+            );
+        }
+    }
+
+    /// Returns when the client has to wake up to run the scenario or handle expired requests
+    fn next_wakeup(&self) -> Option<Instant> {
+        [
+            self.sessions.next_deadline(),
+            self.runner.next_wakeup(&self.pipeline_limits, &self.router),
+            self.discovery.as_ref().and_then(DiscoverySweep::next_sweep),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Waits for the next command or message, or for the next wakeup, and handles it, then takes care of the
+    /// requests whose deadline has expired in the meantime.
+    /// Returns `false` when the client has to stop.
+    /// A disconnected listener is reported once and then ignored, as the scenario can still run its
+    /// remaining steps: they fail as their responses never arrive
+    /// # Errors
+    /// Returns an error if the command channel is disconnected, as the client can no longer be stopped
+    fn handle_next_event(&mut self) -> Result<bool, ClientError> {
+        let timeout = self
+            .next_wakeup()
+            .map_or(IDLE_TIMEOUT, |wakeup| {
+                wakeup.saturating_duration_since(Instant::now())
+            });
+        let listener_rx = if self.listener_connected {
+            self.get_listener_to_server_logic_rx().clone()
+        } else {
+            never()
+        };
+
+        let keep_running = select_biased! {
+            recv(self.get_server_command_rx()) -> command => {
+                match command {
+                    Ok(command) => self.process_command(command),
+                    Err(_) => return Err(ClientError::Disconnected("command")),
+                }
+            },
+            recv(listener_rx) -> message => {
+                if let Ok(message) = message {
+                    self.process_message(&message);
+                } else {
+                    self.listener_connected = false;
+                    self.report_error(ClientError::Disconnected("listener"));
+                }
+                true
+            },
+            default(timeout) => true,
+        };
+
+        self.handle_expired_requests();
+        Ok(keep_running)
+    }
+
+    /// Handles a command of the node. Returns `false` on `ClientCommand::Quit`
+    fn process_command(&mut self, command: ClientCommand) -> bool {
+        match command {
+            ClientCommand::Quit => return false,
+            ClientCommand::SendRequest { destination, request } => {
+                self.send_request(destination, request);
+            }
+            ClientCommand::Pause => {
+                log::info!("Client {}: scenario paused", self.node_id);
+                self.runner.pause();
+            }
+            ClientCommand::Resume => {
+                log::info!("Client {}: scenario resumed", self.node_id);
+                self.runner.resume();
+            }
+            ClientCommand::SetSleepTime(sleep_time) => {
+                log::info!("Client {}: waiting {sleep_time:?} after every scenario request", self.node_id);
+                self.runner.set_sleep_time(sleep_time);
+            }
+            ClientCommand::ReplaceScenario(scenario) => self.replace_scenario(scenario),
+            ClientCommand::QueryState(reply_tx) => {
+                if reply_tx.send(self.state()).is_err() {
+                    log::warn!("Client {}: nobody is waiting for the state", self.node_id);
+                }
+            }
+        }
+        true
+    }
+
+    /// Ends the running scenario, publishing its report, and starts `scenario`.
+    /// The pause and the sleep time set with the commands are kept
+    fn replace_scenario(&mut self, scenario: Scenario) {
+        log::info!("Client {}: replacing the scenario", self.node_id);
+        if let Some(report) = self.runner.take_report(true) {
+            self.publish_report(report);
+        }
+
+        let mut runner = ScenarioRunner::new(scenario);
+        // The late responses to the old scenario must not satisfy the steps of the new one
+        runner.retire_sessions(self.runner.unfinished_sessions());
+        if self.runner.is_paused() {
+            runner.pause();
+        }
+        if let Some(sleep_time) = self.runner.sleep_time() {
+            runner.set_sleep_time(sleep_time);
         }
+        self.runner = runner;
     }
 
-    fn process_text_response(&mut self, source: NodeId, text_response: &TextResponse) {
+    fn state(&self) -> ClientState {
+        ClientState {
+            node_id: self.node_id,
+            scenario: self.runner.progress(),
+            pending_requests: self.sessions.pending_count(),
+            listener_connected: self.listener_connected,
+        }
+    }
+
+    /// Sends `request` to `destination` with a fresh `session_id`, registering it in the
+    /// session table if a response is expected. Returns the used `session_id`
+    fn send_request(&mut self, destination: NodeId, request: RequestType) -> u64 {
+        let session_id = self.new_session_id();
+
+        match &request {
+            RequestType::ChatRequest(ChatRequest::Register) => {
+                self.chat_state.set_registered(destination);
+            }
+            RequestType::ChatRequest(ChatRequest::SendMessage { to, message, .. }) => {
+                self.chat_state.queue(destination, session_id, *to, message.clone());
+
+                // A server drops the messages of the clients that are not registered to it
+                if !self.chat_state.is_registered(destination) {
+                    log::info!("Registering to {destination} before sending a message to {to}");
+                    self.send_request(destination, RequestType::ChatRequest(ChatRequest::Register));
+                }
+            }
+            _ => {}
+        }
+
+        if Self::expects_response(&request) {
+            let deadline = Instant::now() + self.retry_policy.timeout;
+            let pending = PendingRequest::new(destination, request.clone(), deadline);
+            self.sessions.insert(session_id, pending);
+        }
+
+        let is_message = Self::is_chat_message(&request);
+        self.transmit_request(session_id, destination, request);
+        if is_message {
+            self.chat_state.mark_sent(destination, session_id);
+        }
+        session_id
+    }
+
+    /// Sends a `DiscoveryRequest` to every candidate server, if a sweep is due, and tells the router
+    /// whether new servers can still be discovered
+    fn sweep_discovery(&mut self) {
+        let candidates = self
+            .discovery
+            .as_mut()
+            .map(|discovery| discovery.take_due(Instant::now()))
+            .unwrap_or_default();
+        for candidate in candidates {
+            self.send_request(candidate, RequestType::DiscoveryRequest(()));
+        }
+
+        // Servers can still be discovered while a sweep is scheduled or a discovery request is pending
+        let sweep_scheduled = self
+            .discovery
+            .as_ref()
+            .and_then(DiscoverySweep::next_sweep)
+            .is_some();
+        let discovery_pending = self
+            .sessions
+            .any_pending(|pending| matches!(pending.request, RequestType::DiscoveryRequest(_)));
+        self.router.set_discovering(sweep_scheduled || discovery_pending);
+    }
+
+    /// Requests the text list of the servers to crawl
+    fn start_crawl(&mut self) {
+        let servers = self.crawler.as_mut().map(Crawler::start).unwrap_or_default();
+        for server in servers {
+            self.send_request(server, RequestType::TextRequest(TextRequest::TextList));
+        }
+    }
+
+    fn publish_finished_crawls(&mut self) {
+        let finished = self.crawler.as_mut().map(Crawler::take_finished).unwrap_or_default();
+        for (server, stats) in finished {
+            log::info!("Crawl of {server} finished: {stats:?}");
+            self.emit_event(ClientEvent::CrawlFinished { server, stats });
+        }
+    }
+
+    /// Retries, delays or gives up the requests whose deadline has expired
+    fn handle_expired_requests(&mut self) {
+        let now = Instant::now();
+        for (session_id, mut pending) in self.sessions.take_expired(now) {
+            match pending.state {
+                PendingState::BackingOff => self.retransmit_request(session_id, pending),
+                PendingState::AwaitingResponse
+                    if pending.attempt < self.retry_policy.max_attempts =>
+                {
+                    let backoff = self.retry_policy.backoff(pending.attempt);
+                    log::warn!(
+                        "Session {session_id}: no response from {} to {:?} after attempt {}, retrying in {backoff:?}",
+                        pending.destination,
+                        pending.request,
+                        pending.attempt
+                    );
+                    if backoff.is_zero() {
+                        self.retransmit_request(session_id, pending);
+                    } else {
+                        pending.state = PendingState::BackingOff;
+                        pending.deadline = now + backoff;
+                        self.sessions.insert(session_id, pending);
+                    }
+                }
+                PendingState::AwaitingResponse => self.fail_request(session_id, &pending),
+            }
+        }
+    }
+
+    /// Sends again a request pending with `old_session_id`, using a new session
+    fn retransmit_request(&mut self, old_session_id: u64, mut pending: PendingRequest) {
+        let session_id = self.new_session_id();
+        let now = Instant::now();
+        pending.attempt += 1;
+        pending.attempt_sent_at = now;
+        pending.state = PendingState::AwaitingResponse;
+        pending.deadline = now + self.retry_policy.timeout;
+
+        let destination = pending.destination;
+        let request = pending.request.clone();
+        let origin_session = pending.origin_session(old_session_id);
+        log::info!(
+            "Session {old_session_id}: attempt {} sent with session {session_id}",
+            pending.attempt
+        );
+        self.sessions.supersede(old_session_id, session_id, pending);
+
+        let is_message = Self::is_chat_message(&request);
+        self.transmit_request(session_id, destination, request);
+        if is_message {
+            self.chat_state.mark_sent(destination, origin_session);
+        }
+    }
+
+    /// Gives up a request that exhausted its attempts, reporting the failure
+    fn fail_request(&mut self, session_id: u64, pending: &PendingRequest) {
+        self.sessions.finish(session_id, pending);
+        self.request_ended_without_response(
+            pending.origin_session(session_id),
+            pending.destination,
+            &pending.request,
+        );
+
+        let failure = RequestFailure {
+            session_id,
+            destination: pending.destination,
+            request: pending.request.clone(),
+            attempts: pending.attempt,
+            elapsed: pending.sent_at.elapsed(),
+        };
+        log::error!("Request failed: {failure:?}");
+        self.emit_event(ClientEvent::RequestFailed(failure));
+    }
+
+    /// Releases what was waiting for the response of a request that will never arrive
+    fn request_ended_without_response(
+        &mut self,
+        origin_session: u64,
+        destination: NodeId,
+        request: &RequestType,
+    ) {
+        match request {
+            RequestType::MediaRequest(MediaRequest::Media(name)) => {
+                self.media_unavailable(&MediaKey::new(destination, name));
+            }
+            RequestType::TextRequest(TextRequest::TextList) => {
+                if let Some(crawler) = &mut self.crawler {
+                    crawler.list_unavailable(destination);
+                }
+            }
+            RequestType::TextRequest(TextRequest::Text(name)) => {
+                if let Some(crawler) = &mut self.crawler {
+                    crawler.text_unavailable(destination, name);
+                }
+            }
+            RequestType::ChatRequest(ChatRequest::SendMessage { .. }) => {
+                self.chat_state.fail(destination, origin_session);
+            }
+            RequestType::DiscoveryRequest(()) => {
+                let was_known = self.server_directory.forget(destination);
+                if was_known {
+                    log::warn!("Server {destination} does not answer anymore, removed from the directory");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn new_session_id(&self) -> u64 {
+        let mut rng = rand::rng();
+        let mut session_id = rng.next_u64();
+        while !self.sessions.is_free(session_id) {
+            session_id = rng.next_u64();
+        }
+        session_id
+    }
+
+    fn transmit_request(&self, session_id: u64, destination: NodeId, request: RequestType) {
+        let message = self.create_message(session_id, destination, MessageType::Request(request));
+        if let Err(error) = self.send_message_to_transmitter(message) {
+            self.report_error(error);
+        }
+    }
+
+    /// Logs the scenario report, writes it to `report_path` and reports it as an event
+    fn publish_report(&self, report: ScenarioReport) {
+        let status = match &report.error {
+            Some(error) => format!(" (scenario {error})"),
+            None if !report.completed => " (scenario not completed)".to_string(),
+            None => String::new(),
+        };
+        log::info!(
+            "Scenario report: {} expectations passed, {} failed{status}",
+            report.passed,
+            report.failed
+        );
+
+        if let Some(path) = &self.report_path {
+            let written = serde_json::to_string_pretty(&report)
+                .map_err(std::io::Error::from)
+                .and_then(|json| std::fs::write(path, json));
+            if let Err(error) = written {
+                log::error!("Cannot write scenario report to {}: {error}", path.display());
+            }
+        }
+
+        self.emit_event(ClientEvent::ScenarioFinished(report));
+    }
+
+    /// Reports `event` on the event channel, if any
+    fn emit_event(&self, event: ClientEvent) {
+        if let Some(event_tx) = &self.event_tx {
+            if let Err(error) = event_tx.send(event) {
+                log::warn!("Cannot report client event. Error: {error:?}");
+            }
+        }
+    }
+
+    /// Logs a recoverable `error` and reports it as an event
+    fn report_error(&self, error: ClientError) {
+        log::error!("Client {}: {error}", self.node_id);
+        self.emit_event(ClientEvent::Error(error));
+    }
+
+    /// Returns `true` if the server is expected to answer `request`
+    fn expects_response(request: &RequestType) -> bool {
+        !matches!(request, RequestType::ChatRequest(ChatRequest::Register))
+    }
+
+    fn is_chat_message(request: &RequestType) -> bool {
+        matches!(
+            request,
+            RequestType::ChatRequest(ChatRequest::SendMessage { .. })
+        )
+    }
+
+    /// Returns `true` if `response` can be pushed by a server without a matching request
+    fn is_push(response: &ResponseType) -> bool {
+        matches!(
+            response,
+            ResponseType::ChatResponse(ChatResponse::MessageFrom { .. })
+        )
+    }
+
+    fn process_text_response(
+        &mut self,
+        source: NodeId,
+        request: Option<&RequestType>,
+        text_response: &TextResponse,
+    ) {
         match text_response {
             TextResponse::TextList(list) => {
                 log::info!("Received TextList: {list:?}");
 
-                /*
-                let mut rng = rand::rng();
-                let text_resource = rng.random_range(0..list.len());
-                let text_resource = list
-                    .get(text_resource)
-                    .unwrap_or_else(|_| panic!("Resource at index {text_resource} not found"));
-
-                let session_id = rng.next_u64();
-                let request = Message {
-                    source: self.node_id,
-                    destination: source,
-                    session_id,
-                    content: MessageType::Request(
-                        RequestType::TextRequest(
-                            TextRequest::Text(text_resource.clone())
-                        )
-                    ),
-                };
-                self
-                    .client_logic_to_transmitter_tx
-                    .send(request)
-                    .unwrap_or_else(|_| panic!("Cannot communicate with transmitter"));
-                 */
+                if let Some(crawler) = &mut self.crawler {
+                    for name in crawler.on_list(source, list) {
+                        self.send_request(source, RequestType::TextRequest(TextRequest::Text(name)));
+                    }
+                }
             }
             TextResponse::Text(text) => {
                 log::info!("Received Text: {text}");
-                let re = Regex::new(r"\{\{\s*([^{}\s]+\.(png|jpe?g))\s*}}").unwrap();
+                let (medias, texts): (Vec<Reference>, Vec<Reference>) = parse_references(text)
+                    .into_iter()
+                    .partition(|reference| reference.kind == ReferenceKind::Media);
 
-                let mut medias = Vec::new();
-                for cap in re.captures_iter(text) {
-                    medias.push(cap[1].to_string());
+                log::info!("Medias found to request: {medias:?}");
+                if !texts.is_empty() {
+                    log::info!("Linked text documents: {texts:?}");
                 }
 
-                log::info!("Medias found to request: {medias:?}");
+                let name = match request {
+                    Some(RequestType::TextRequest(TextRequest::Text(name))) => name.as_str(),
+                    _ => "unknown",
+                };
 
-                let mut rng = rand::rng();
+                // The crawler and the document are updated first, as cached media are resolved while fetching
+                if let Some(crawler) = &mut self.crawler {
+                    for linked in crawler.on_text(source, name, text).unwrap_or_default() {
+                        self.send_request(source, RequestType::TextRequest(TextRequest::Text(linked)));
+                    }
+                }
+                if let Some(documents) = &mut self.documents {
+                    documents.start(source, name, text);
+                }
 
                 for media in medias {
-                    let session_id = rng.next_u64();
-                    let request = Message {
-                        source: self.node_id,
-                        destination: source,
-                        session_id,
-                        content: MessageType::Request(
-                            RequestType::MediaRequest(
-                                MediaRequest::Media(media)
-                            )
-                        ),
-                    };
-                    self
-                        .client_logic_to_transmitter_tx
-                        .send(request)
-                        .unwrap_or_else(|_| panic!("Cannot communicate with transmitter"));
+                    self.fetch_media(source, &media.name);
+                }
+
+                if let Some(documents) = &mut self.documents {
+                    let complete = documents.take_complete();
+                    self.render_documents(&complete);
                 }
             }
             TextResponse::NotFound(filename) => {
                 log::warn!("Text file '{filename}' not found. Full response: {text_response:?}");
+                if let Some(crawler) = &mut self.crawler {
+                    crawler.text_unavailable(source, filename);
+                }
             }
         }
     }
 
-    #[allow(clippy::unused_self)]
-    fn process_media_response(&mut self, _source: NodeId, media_response: &MediaResponse) {
+    fn process_media_response(
+        &mut self,
+        source: NodeId,
+        request: Option<&RequestType>,
+        media_response: &MediaResponse,
+    ) {
         match media_response {
             MediaResponse::MediaList(list) => {
                 log::info!("Received MediaList: {list:?}");
             }
             MediaResponse::Media(media) => {
-                Self::open_png_from_bytes(media.clone()).expect("Cannot open received media");
+                let name = match request {
+                    Some(RequestType::MediaRequest(MediaRequest::Media(name))) => name.as_str(),
+                    _ => "unknown",
+                };
+                log::info!("Received media '{name}' of {} bytes from {source}", media.len());
+                let key = MediaKey::new(source, name);
+                match validate_media(media, &self.media_limits) {
+                    Ok(info) => {
+                        log::info!("Media '{name}' is a {}x{} {:?}", info.width, info.height, info.format);
+                        let origin = self.media_fallbacks.take(&key).origin;
+                        if origin.server != source {
+                            log::info!("Media '{name}' requested from {} served by {source}", origin.server);
+                        }
+                        self.media_cache.insert(origin.clone(), media);
+                        log::debug!("Media cache holds {} bytes", self.media_cache.used());
+                        self.emit_event(ClientEvent::MediaFetched {
+                            name: name.to_string(),
+                            requested_from: origin.server,
+                            served_by: source,
+                        });
+                        if let Err(error) = self.media_sink.store(name, media) {
+                            log::error!("Cannot store media '{name}'. Error: {error}");
+                        }
+                        self.deliver_media(&origin, &Arc::from(media.as_slice()));
+                    }
+                    Err(error) => {
+                        self.media_unavailable(&key);
+                        self.reject_media(name, source, media, error);
+                    }
+                }
             }
             MediaResponse::NotFound(media_name) => {
+                self.media_unavailable(&MediaKey::new(source, media_name));
                 log::warn!("Media file '{media_name}' not found. Full response: {media_name:?}");
             }
         }
     }
 
-    fn open_png_from_bytes(png_data: Vec<u8>) -> std::io::Result<()> {
-        use image::io::Reader as ImageReader;
-        use std::process::Command;
-        use std::env::temp_dir;
+    /// Requests the media `name` from `server`, unless it is cached or already being requested
+    fn fetch_media(&mut self, server: NodeId, name: &str) {
+        let key = MediaKey::new(server, name);
+        match self.media_cache.lookup(&key, None) {
+            MediaLookup::Cached(data) => {
+                log::info!("Media '{name}' of {server} served from cache");
+                self.deliver_media(&key, &data);
+            }
+            MediaLookup::InFlight(session_id) => {
+                log::info!("Media '{name}' of {server} already requested with session {session_id}");
+            }
+            MediaLookup::Missing => {
+                let request = RequestType::MediaRequest(MediaRequest::Media(name.to_string()));
+                let session_id = self.send_request(server, request);
+                self.media_cache.mark_in_flight(key, session_id);
+            }
+        }
+    }
 
-        // Decode PNG image
-        let img = ImageReader::new(std::io::Cursor::new(png_data))
-            .with_guessed_format()
-            .unwrap()
-            .decode()
-            .unwrap();
+    /// Hands a valid media, just received or cached, to the crawler and to the documents waiting for it.
+    /// The media sink only gets the received ones
+    fn deliver_media(&mut self, key: &MediaKey, data: &Arc<[u8]>) {
+        if let Some(crawler) = &mut self.crawler {
+            crawler.on_media(key, data);
+        }
 
-        // Create a temporary file path
-        let mut temp_path = temp_dir();
-        let mut rng = rand::rng();
-        let num = rng.random_range(0..=100);
-        let path = format!("image_{num}.png");
-        temp_path.push(path);
+        if let Some(documents) = &mut self.documents {
+            let complete = documents.resolve_media(key, Some(data));
+            self.render_documents(&complete);
+        }
+    }
 
-        // Save the image to a file
-        img.save(&temp_path).unwrap();
+    /// Records that the media could not be obtained with the request `attempt`, asking another server if the
+    /// fallback policy allows it
+    fn media_unavailable(&mut self, attempt: &MediaKey) {
+        let fallback = self.media_fallbacks.take(attempt);
+        let candidates = self
+            .media_fallback
+            .servers
+            .iter()
+            .copied()
+            .chain(self.server_directory.servers_of_type(&ServerType::Media));
+        if let Some(server) = MediaFallbacks::next_server(&fallback, candidates, &self.media_fallback) {
+            let name = fallback.origin.name.clone();
+            log::info!("Media '{name}' not obtained from {}, asking {server}", attempt.server);
+            let session_id = self.send_request(server, RequestType::MediaRequest(MediaRequest::Media(name)));
+            self.media_cache.mark_in_flight(fallback.origin.clone(), session_id);
+            self.media_fallbacks.attempt(server, fallback);
+            return;
+        }
 
-        // Open the image using the default system viewer
-        #[cfg(target_os = "windows")]
-        Command::new("cmd").args(&["/C", temp_path.to_str().unwrap()]).spawn()?;
+        let key = &fallback.origin;
+        if !fallback.tried.is_empty() {
+            log::warn!("Media '{}' not obtained from any of {:?}", key.name, fallback.tried);
+        }
+        self.media_cache.cancel(key);
+        if let Some(crawler) = &mut self.crawler {
+            crawler.media_unavailable(key);
+        }
 
-        #[cfg(target_os = "macos")]
-        Command::new("open").arg(temp_path.to_str().unwrap()).spawn()?;
+        if let Some(documents) = &mut self.documents {
+            let complete = documents.resolve_media(key, None);
+            self.render_documents(&complete);
+        }
+    }
 
-        #[cfg(target_os = "linux")]
-        Command::new("xdg-open").arg(temp_path.to_str().unwrap()).spawn()?;
+    fn render_documents(&self, complete: &[PendingDocument]) {
+        let Some(documents) = &self.documents else {
+            return;
+        };
+
+        for document in complete {
+            match documents.render(document) {
+                Ok(path) => {
+                    log::info!("Document '{}' rendered to {}", document.name, path.display());
+                    self.emit_event(ClientEvent::DocumentRendered {
+                        name: document.name.clone(),
+                        source: document.server,
+                        path,
+                    });
+                }
+                Err(error) => {
+                    log::error!("Cannot render document '{}'. Error: {error}", document.name);
+                }
+            }
+        }
+    }
 
-        Ok(())
+    /// Reports an invalid media, keeping a copy of it in the quarantine directory if any
+    fn reject_media(&self, name: &str, source: NodeId, data: &[u8], error: MediaError) {
+        log::error!("Media '{name}' from {source} rejected: {error}");
+
+        let quarantined = self.quarantine_dir.as_ref().and_then(|directory| {
+            quarantine(directory, name, source, data, &error)
+                .inspect_err(|io_error| {
+                    log::error!("Cannot quarantine media '{name}'. Error: {io_error}");
+                })
+                .ok()
+        });
+
+        self.emit_event(ClientEvent::MediaRejected {
+            name: name.to_string(),
+            source,
+            error,
+            quarantined,
+        });
     }
 
-    #[allow(clippy::unused_self)]
-    fn process_chat_response(&mut self, _source: NodeId, chat_response: &ChatResponse) {
+    fn process_chat_response(
+        &mut self,
+        source: NodeId,
+        origin_session: Option<u64>,
+        chat_response: &ChatResponse,
+    ) {
         match chat_response {
             ChatResponse::ClientList(list) => {
                 log::info!("Received ClientList: {list:?}");
+                self.chat_state.set_clients(source, list.clone());
+                self.emit_event(ClientEvent::ClientListReceived {
+                    server: source,
+                    clients: list.clone(),
+                });
             }
             ChatResponse::MessageFrom { from, message } => {
                 log::info!("Received 'MessageFrom' from {from}, content: {message}");
+                self.chat_state.receive(source, *from, message.clone());
+                self.emit_event(ClientEvent::MessageReceived {
+                    server: source,
+                    from: *from,
+                    message: message.clone(),
+                });
             }
             ChatResponse::MessageSent => {
                 log::info!("Received MessageSent");
+                match origin_session {
+                    Some(origin_session) => self.chat_state.acknowledge(source, origin_session),
+                    None => log::warn!("MessageSent from {source} does not match any sent message"),
+                }
             }
         }
     }
 
-    #[allow(clippy::unused_self)]
-    fn process_discovery_response(&mut self, source: NodeId, server_type: &ServerType) {
+    fn process_discovery_response(
+        &mut self,
+        source: NodeId,
+        server_type: &ServerType,
+        latency: Option<Duration>,
+    ) {
         log::info!("Discovery response from {source}: {server_type:?}");
+        if self.server_directory.record(source, server_type, latency) {
+            self.emit_event(ClientEvent::ServerDiscovered {
+                server: source,
+                server_type: server_type.clone(),
+            });
+        }
     }
 }
\ No newline at end of file
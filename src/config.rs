@@ -0,0 +1,147 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use crossbeam_channel::Sender;
+use crate::chat::ChatState;
+use crate::crawl::CrawlConfig;
+use crate::directory::{DiscoveryConfig, ServerDirectory};
+use crate::document::DocumentConfig;
+use crate::event::ClientEvent;
+use crate::media::MediaLimits;
+use crate::media_fallback::MediaFallbackPolicy;
+use crate::media_sink::{MediaSink, ViewerSink};
+use crate::routing::RoutingStrategy;
+use crate::runtime::RestartPolicy;
+
+/// How requests that do not receive a response in time are sent again
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Maximum number of times a request is sent, including the first attempt
+    pub max_attempts: u32,
+    /// Time waited for a response before an attempt is considered lost
+    pub timeout: Duration,
+    /// Delay between the first timeout and the second attempt
+    pub initial_backoff: Duration,
+    /// Factor applied to the delay after every further timeout
+    pub backoff_multiplier: u32,
+    /// Upper bound of the delay between two attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            timeout: Duration::from_secs(5),
+            initial_backoff: Duration::from_millis(500),
+            backoff_multiplier: 2,
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay to wait before sending the attempt following `attempt`
+    #[must_use]
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        self.backoff_multiplier
+            .checked_pow(attempt.saturating_sub(1))
+            .and_then(|factor| self.initial_backoff.checked_mul(factor))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// How many scenario requests can wait for their response at the same time.
+/// The default limits give the stop-and-wait behaviour: one request at a time
+#[derive(Debug, Clone)]
+pub struct PipelineLimits {
+    /// Maximum number of requests in flight
+    pub max_in_flight: usize,
+    /// Maximum number of requests in flight towards the same destination
+    pub max_in_flight_per_destination: usize,
+}
+
+impl Default for PipelineLimits {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 1,
+            max_in_flight_per_destination: 1,
+        }
+    }
+}
+
+/// Optional settings of a `DibClient`
+pub struct ClientConfig {
+    pub retry_policy: RetryPolicy,
+    pub pipeline_limits: PipelineLimits,
+    /// Channel on which the client reports its `ClientEvent`s
+    pub event_tx: Option<Sender<ClientEvent>>,
+    /// File where the `ScenarioReport` is written as JSON when the scenario ends
+    pub report_path: Option<PathBuf>,
+    /// Where the received media end up. By default they are opened with the system viewer
+    pub media_sink: Box<dyn MediaSink>,
+    pub media_limits: MediaLimits,
+    /// Directory where the media that fail validation are kept for later inspection
+    pub quarantine_dir: Option<PathBuf>,
+    /// Maximum number of bytes of media kept in memory to serve repeated references. 0 disables the cache
+    pub media_cache_budget: usize,
+    /// Which other servers are asked for a media that is not found
+    pub media_fallback: MediaFallbackPolicy,
+    /// How the logic thread is restarted if it crashes
+    pub restart_policy: RestartPolicy,
+    /// If set, every received text is rendered together with its media into a document
+    pub documents: Option<DocumentConfig>,
+    /// If set, the client mirrors the listed text servers, and the ones whose text list it receives
+    pub crawl: Option<CrawlConfig>,
+    /// Where the client keeps the state of its chats. Keep a clone of it to query the state while the client runs
+    pub chat_state: ChatState,
+    /// If set, the candidate servers are probed with `DiscoveryRequest`s to fill `server_directory`
+    pub discovery: Option<DiscoveryConfig>,
+    /// Where the client keeps the servers it discovered. Keep a clone of it to query it while the client runs
+    pub server_directory: ServerDirectory,
+    /// How the scenario steps addressed to a server type choose their destination
+    pub routing_strategy: RoutingStrategy,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            retry_policy: RetryPolicy::default(),
+            pipeline_limits: PipelineLimits::default(),
+            event_tx: None,
+            report_path: None,
+            media_sink: Box::new(ViewerSink),
+            media_limits: MediaLimits::default(),
+            quarantine_dir: None,
+            media_cache_budget: 32 * 1024 * 1024,
+            media_fallback: MediaFallbackPolicy::default(),
+            restart_policy: RestartPolicy::default(),
+            documents: None,
+            crawl: None,
+            chat_state: ChatState::new(),
+            discovery: None,
+            server_directory: ServerDirectory::new(),
+            routing_strategy: RoutingStrategy::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_by_the_multiplier() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn backoff_saturates_at_max_backoff() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(6), Duration::from_secs(10));
+        assert_eq!(policy.backoff(40), Duration::from_secs(10));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(10));
+    }
+}
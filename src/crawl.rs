@@ -0,0 +1,279 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use wg_2024::network::NodeId;
+use crate::document::unique_stem;
+use crate::markup::{parse_references, ReferenceKind};
+use crate::media_cache::MediaKey;
+
+/// Which text servers are mirrored, how deep and where
+#[derive(Debug, Clone)]
+pub struct CrawlConfig {
+    /// Servers whose text list is requested when the client starts
+    pub servers: Vec<NodeId>,
+    /// How many text-to-text references are followed from the listed texts. 0 fetches the listed texts only
+    pub max_depth: usize,
+    /// Every server is mirrored in the `{server}` subdirectory, with its texts in `texts` and its media in `media`
+    pub output_dir: PathBuf,
+}
+
+/// Outcome of the crawl of a server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CrawlStats {
+    pub texts: usize,
+    pub media: usize,
+    /// Texts and media that could not be obtained or saved
+    pub missing: usize,
+}
+
+/// Progress of the crawl of a server
+#[derive(Debug, Default)]
+struct ServerCrawl {
+    listing: bool,
+    visited: HashSet<String>,
+    /// Texts requested, with their distance from the text list
+    texts: HashMap<String, usize>,
+    media: HashSet<String>,
+    stats: CrawlStats,
+}
+
+impl ServerCrawl {
+    fn is_done(&self) -> bool {
+        !self.listing && self.texts.is_empty() && self.media.is_empty()
+    }
+}
+
+/// Mirrors the content of text servers, following the references between texts
+#[derive(Debug)]
+pub struct Crawler {
+    config: CrawlConfig,
+    servers: HashMap<NodeId, ServerCrawl>,
+}
+
+impl Crawler {
+    pub fn new(config: CrawlConfig) -> Self {
+        Self {
+            config,
+            servers: HashMap::new(),
+        }
+    }
+
+    /// Starts the crawl of the configured servers. Returns the servers whose text list has to be requested
+    pub fn start(&mut self) -> Vec<NodeId> {
+        let servers = self.config.servers.clone();
+        for server in &servers {
+            self.servers.entry(*server).or_default().listing = true;
+        }
+        servers
+    }
+
+    /// Handles the text list of `server`. Returns the texts to request
+    pub fn on_list(&mut self, server: NodeId, list: &[String]) -> Vec<String> {
+        let crawl = self.servers.entry(server).or_default();
+        crawl.listing = false;
+
+        list.iter()
+            .filter(|name| crawl.visited.insert((*name).clone()))
+            .inspect(|name| {
+                crawl.texts.insert((*name).clone(), 0);
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Handles a text the crawler asked for, saving it. Returns the texts it references
+    /// that have to be requested, or `None` if the text is not part of a crawl
+    pub fn on_text(&mut self, server: NodeId, name: &str, text: &str) -> Option<Vec<String>> {
+        let max_depth = self.config.max_depth;
+        let directory = self.server_dir(server).join("texts");
+        let crawl = self.servers.get_mut(&server)?;
+        let depth = crawl.texts.remove(name)?;
+
+        match save(&directory, server, name, text.as_bytes()) {
+            Ok(path) => {
+                log::info!("Crawl of {server}: text '{name}' saved to {}", path.display());
+                crawl.stats.texts += 1;
+            }
+            Err(error) => {
+                log::error!("Crawl of {server}: cannot save text '{name}'. Error: {error}");
+                crawl.stats.missing += 1;
+            }
+        }
+
+        let mut linked = Vec::new();
+        for reference in parse_references(text) {
+            match reference.kind {
+                ReferenceKind::Media => {
+                    crawl.media.insert(reference.name);
+                }
+                ReferenceKind::Text if depth < max_depth => {
+                    if crawl.visited.insert(reference.name.clone()) {
+                        crawl.texts.insert(reference.name.clone(), depth + 1);
+                        linked.push(reference.name);
+                    }
+                }
+                ReferenceKind::Text => {
+                    log::debug!("Crawl of {server}: '{}' is beyond the maximum depth", reference.name);
+                }
+            }
+        }
+        Some(linked)
+    }
+
+    /// Handles a media, saving it if a crawled text references it
+    pub fn on_media(&mut self, key: &MediaKey, data: &[u8]) {
+        let directory = self.server_dir(key.server).join("media");
+        let Some(crawl) = self.servers.get_mut(&key.server) else {
+            return;
+        };
+        if !crawl.media.remove(&key.name) {
+            return;
+        }
+
+        match save(&directory, key.server, &key.name, data) {
+            Ok(_) => crawl.stats.media += 1,
+            Err(error) => {
+                log::error!("Crawl of {}: cannot save media '{}'. Error: {error}", key.server, key.name);
+                crawl.stats.missing += 1;
+            }
+        }
+    }
+
+    /// Records that the text list of `server` could not be obtained
+    pub fn list_unavailable(&mut self, server: NodeId) {
+        if let Some(crawl) = self.servers.get_mut(&server) {
+            crawl.listing = false;
+        }
+    }
+
+    /// Records that the text `name` of `server` could not be obtained
+    pub fn text_unavailable(&mut self, server: NodeId, name: &str) {
+        if let Some(crawl) = self.servers.get_mut(&server) {
+            if crawl.texts.remove(name).is_some() {
+                crawl.stats.missing += 1;
+            }
+        }
+    }
+
+    /// Records that the media `key` could not be obtained
+    pub fn media_unavailable(&mut self, key: &MediaKey) {
+        if let Some(crawl) = self.servers.get_mut(&key.server) {
+            if crawl.media.remove(&key.name) {
+                crawl.stats.missing += 1;
+            }
+        }
+    }
+
+    /// Returns the servers whose crawl has ended since the last call, with their stats
+    pub fn take_finished(&mut self) -> Vec<(NodeId, CrawlStats)> {
+        let finished: Vec<NodeId> = self
+            .servers
+            .iter()
+            .filter(|(_, crawl)| crawl.is_done())
+            .map(|(server, _)| *server)
+            .collect();
+
+        finished
+            .into_iter()
+            .filter_map(|server| self.servers.remove(&server).map(|crawl| (server, crawl.stats)))
+            .collect()
+    }
+
+    fn server_dir(&self, server: NodeId) -> PathBuf {
+        self.config.output_dir.join(server.to_string())
+    }
+}
+
+/// Writes `data` in `directory` under a name derived from `server` and `name`, which keeps the extension
+/// of `name`. A server cannot write outside of `directory`, and different names never share a file
+fn save(directory: &Path, server: NodeId, name: &str, data: &[u8]) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    let path = directory.join(mirror_file_name(server, name));
+    std::fs::write(&path, data)?;
+    Ok(path)
+}
+
+/// Returns the name of the mirrored file `name` of `server`, keeping its extension if it is alphanumeric
+fn mirror_file_name(server: NodeId, name: &str) -> String {
+    let stem = unique_stem(server, name);
+    match Path::new(name).extension().and_then(|extension| extension.to_str()) {
+        Some(extension) if extension.chars().all(char::is_alphanumeric) => format!("{stem}.{extension}"),
+        _ => stem,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A crawler of server 1 writing in a fresh temporary directory, removed when dropped
+    struct TestCrawler {
+        crawler: Crawler,
+        output_dir: PathBuf,
+    }
+
+    impl TestCrawler {
+        fn new(test: &str, max_depth: usize) -> Self {
+            let output_dir = std::env::temp_dir().join(format!("ap_client_crawl_{test}_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&output_dir);
+            let mut crawler = Crawler::new(CrawlConfig {
+                servers: vec![1],
+                max_depth,
+                output_dir: output_dir.clone(),
+            });
+            assert_eq!(crawler.start(), [1]);
+            Self { crawler, output_dir }
+        }
+    }
+
+    impl Drop for TestCrawler {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.output_dir);
+        }
+    }
+
+    #[test]
+    fn stops_following_references_at_the_maximum_depth() {
+        let mut test = TestCrawler::new("depth", 1);
+        let crawler = &mut test.crawler;
+        assert_eq!(crawler.on_list(1, &["a.txt".to_string()]), ["a.txt"]);
+        assert_eq!(crawler.on_text(1, "a.txt", "see {{b.txt}}").unwrap(), ["b.txt"]);
+        assert!(crawler.on_text(1, "b.txt", "see {{c.txt}}").unwrap().is_empty());
+        assert!(crawler.on_text(1, "c.txt", "never requested").is_none());
+
+        let finished = crawler.take_finished();
+        assert_eq!(finished, [(1, CrawlStats { texts: 2, media: 0, missing: 0 })]);
+    }
+
+    #[test]
+    fn requests_every_text_once_despite_cycles() {
+        let mut test = TestCrawler::new("cycles", 10);
+        let crawler = &mut test.crawler;
+        assert_eq!(crawler.on_list(1, &["a.txt".to_string(), "b.txt".to_string()]), ["a.txt", "b.txt"]);
+        assert!(crawler.on_text(1, "a.txt", "{{b.txt}} {{a.txt}}").unwrap().is_empty());
+        assert!(crawler.on_text(1, "b.txt", "{{a.txt}}").unwrap().is_empty());
+
+        assert_eq!(crawler.take_finished(), [(1, CrawlStats { texts: 2, media: 0, missing: 0 })]);
+    }
+
+    #[test]
+    fn same_file_names_do_not_overwrite_each_other() {
+        let mut test = TestCrawler::new("collisions", 0);
+        let crawler = &mut test.crawler;
+        let list = ["docs/a.txt".to_string(), "a.txt".to_string()];
+        assert_eq!(crawler.on_list(1, &list).len(), 2);
+        crawler.on_text(1, "docs/a.txt", "first");
+        crawler.on_text(1, "a.txt", "second");
+
+        let texts = test.output_dir.join("1").join("texts");
+        let mut contents: Vec<String> = std::fs::read_dir(&texts)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, ["first", "second"]);
+
+        assert_ne!(mirror_file_name(1, "a.png"), mirror_file_name(2, "a.png"));
+        assert!(mirror_file_name(1, "a.png").ends_with(".png"));
+        assert!(!mirror_file_name(1, "../../a.png").contains('/'));
+    }
+}
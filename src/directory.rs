@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::mem::discriminant;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime};
+use messages::ServerType;
+use wg_2024::network::NodeId;
+
+/// Which nodes are probed with a `DiscoveryRequest`, and how often
+#[derive(Debug, Clone)]
+pub struct DiscoveryConfig {
+    /// Nodes that may be servers. The ones that are not do not answer, or answer with an error
+    pub candidates: Vec<NodeId>,
+    /// If set, the candidates are probed again periodically, otherwise only when the client starts
+    pub interval: Option<Duration>,
+}
+
+/// What the client knows about a server that answered a `DiscoveryRequest`
+#[derive(Debug, Clone)]
+pub struct ServerEntry {
+    pub server_type: ServerType,
+    /// When the server answered the last time
+    pub last_seen: SystemTime,
+    /// Time between the last `DiscoveryRequest` and its response
+    pub latency: Option<Duration>,
+}
+
+/// Directory of the known servers, shared between the client and the embedding application.
+/// Cloning it gives another handle to the same directory
+#[derive(Debug, Clone, Default)]
+pub struct ServerDirectory {
+    servers: Arc<RwLock<HashMap<NodeId, ServerEntry>>>,
+}
+
+impl ServerDirectory {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the known servers in ascending order, with what is known about them
+    #[must_use]
+    pub fn all(&self) -> Vec<(NodeId, ServerEntry)> {
+        let mut servers: Vec<(NodeId, ServerEntry)> = self
+            .read()
+            .iter()
+            .map(|(server, entry)| (*server, entry.clone()))
+            .collect();
+        servers.sort_unstable_by_key(|(server, _)| *server);
+        servers
+    }
+
+    #[must_use]
+    pub fn get(&self, server: NodeId) -> Option<ServerEntry> {
+        self.read().get(&server).cloned()
+    }
+
+    /// Returns the known servers of type `server_type`, in ascending order
+    #[must_use]
+    pub fn servers_of_type(&self, server_type: &ServerType) -> Vec<NodeId> {
+        let mut servers: Vec<NodeId> = self
+            .read()
+            .iter()
+            .filter(|(_, entry)| discriminant(&entry.server_type) == discriminant(server_type))
+            .map(|(server, _)| *server)
+            .collect();
+        servers.sort_unstable();
+        servers
+    }
+
+    /// Returns a server of type `server_type`, preferring the one that answered the fastest
+    #[must_use]
+    pub fn any_of_type(&self, server_type: &ServerType) -> Option<NodeId> {
+        self.read()
+            .iter()
+            .filter(|(_, entry)| discriminant(&entry.server_type) == discriminant(server_type))
+            .min_by_key(|(server, entry)| (entry.latency.unwrap_or(Duration::MAX), **server))
+            .map(|(server, _)| *server)
+    }
+
+    /// Records that `server` answered a `DiscoveryRequest` with `server_type`.
+    /// Returns `true` if the server was unknown or its type changed
+    pub(crate) fn record(&self, server: NodeId, server_type: &ServerType, latency: Option<Duration>) -> bool {
+        let entry = ServerEntry {
+            server_type: server_type.clone(),
+            last_seen: SystemTime::now(),
+            latency,
+        };
+        let previous = self.write().insert(server, entry);
+        previous.is_none_or(|previous| discriminant(&previous.server_type) != discriminant(server_type))
+    }
+
+    /// Removes `server`, which stopped answering. Returns `true` if it was known
+    pub(crate) fn forget(&self, server: NodeId) -> bool {
+        self.write().remove(&server).is_some()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<NodeId, ServerEntry>> {
+        self.servers.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<NodeId, ServerEntry>> {
+        self.servers.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Schedules the discovery sweeps
+#[derive(Debug)]
+pub struct DiscoverySweep {
+    config: DiscoveryConfig,
+    next_sweep: Option<Instant>,
+}
+
+impl DiscoverySweep {
+    pub fn new(config: DiscoveryConfig) -> Self {
+        Self {
+            config,
+            next_sweep: Some(Instant::now()),
+        }
+    }
+
+    /// Returns when the next sweep is due
+    pub fn next_sweep(&self) -> Option<Instant> {
+        self.next_sweep
+    }
+
+    /// Returns the candidates to probe if a sweep is due at `now`, scheduling the following one
+    pub fn take_due(&mut self, now: Instant) -> Vec<NodeId> {
+        match self.next_sweep {
+            Some(next_sweep) if next_sweep <= now => {
+                self.next_sweep = self.config.interval.map(|interval| now + interval);
+                self.config.candidates.clone()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_and_forgets_servers() {
+        let directory = ServerDirectory::new();
+        assert!(directory.record(2, &ServerType::Text, None));
+        assert!(!directory.record(2, &ServerType::Text, Some(Duration::from_millis(5))));
+        assert!(directory.record(2, &ServerType::Media, None));
+        assert!(directory.record(1, &ServerType::Text, None));
+
+        let servers: Vec<NodeId> = directory.all().into_iter().map(|(server, _)| server).collect();
+        assert_eq!(servers, [1, 2]);
+        assert_eq!(directory.servers_of_type(&ServerType::Text), [1]);
+
+        assert!(directory.forget(2));
+        assert!(!directory.forget(2));
+        assert!(directory.get(2).is_none());
+    }
+
+    #[test]
+    fn handles_share_the_same_directory() {
+        let directory = ServerDirectory::new();
+        let handle = directory.clone();
+        directory.record(1, &ServerType::Chat, None);
+        assert_eq!(handle.servers_of_type(&ServerType::Chat), [1]);
+    }
+
+    #[test]
+    fn any_of_type_prefers_the_fastest_then_the_lowest_id() {
+        let directory = ServerDirectory::new();
+        directory.record(1, &ServerType::Text, None);
+        directory.record(3, &ServerType::Text, Some(Duration::from_millis(10)));
+        directory.record(2, &ServerType::Text, Some(Duration::from_millis(10)));
+        directory.record(4, &ServerType::Text, Some(Duration::from_millis(20)));
+        assert_eq!(directory.any_of_type(&ServerType::Text), Some(2));
+        assert_eq!(directory.any_of_type(&ServerType::Media), None);
+    }
+
+    #[test]
+    fn sweeps_are_due_once_per_interval() {
+        let mut sweep = DiscoverySweep::new(DiscoveryConfig {
+            candidates: vec![1, 2],
+            interval: Some(Duration::from_secs(10)),
+        });
+        let now = Instant::now();
+        assert_eq!(sweep.take_due(now), [1, 2]);
+        assert!(sweep.take_due(now).is_empty());
+        assert_eq!(sweep.next_sweep(), Some(now + Duration::from_secs(10)));
+        assert_eq!(sweep.take_due(now + Duration::from_secs(10)), [1, 2]);
+
+        let mut once = DiscoverySweep::new(DiscoveryConfig {
+            candidates: vec![1],
+            interval: None,
+        });
+        assert_eq!(once.take_due(Instant::now()), [1]);
+        assert_eq!(once.next_sweep(), None);
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use wg_2024::network::NodeId;
+use crate::markup::{parse_references, tokenize, Reference, ReferenceKind, Segment};
+use crate::media_cache::MediaKey;
+use crate::media_sink::{content_hash, write_content_addressed};
+
+/// Output format of the assembled documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DocumentFormat {
+    Html,
+    Markdown,
+}
+
+impl DocumentFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            DocumentFormat::Html => "html",
+            DocumentFormat::Markdown => "md",
+        }
+    }
+}
+
+/// How the media are included in the assembled documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaEmbedding {
+    /// Media are embedded as base64 data URIs, making every document self-contained
+    Base64,
+    /// Media are saved in the `media` subdirectory and linked with relative paths
+    LocalFile,
+}
+
+/// Where and how the received texts are assembled with their media
+#[derive(Debug, Clone)]
+pub struct DocumentConfig {
+    pub output_dir: PathBuf,
+    pub format: DocumentFormat,
+    pub embedding: MediaEmbedding,
+}
+
+/// A text waiting for its media before being rendered
+#[derive(Debug)]
+pub struct PendingDocument {
+    pub server: NodeId,
+    pub name: String,
+    text: String,
+    missing: HashSet<String>,
+    media: HashMap<String, Option<Arc<[u8]>>>,
+}
+
+/// Collects the received texts and their media, rendering every document once all its media are resolved
+#[derive(Debug)]
+pub struct DocumentAssembler {
+    config: DocumentConfig,
+    pending: Vec<PendingDocument>,
+}
+
+impl DocumentAssembler {
+    pub fn new(config: DocumentConfig) -> Self {
+        Self {
+            config,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Registers the text `name` received from `server`, which waits for the media it references
+    pub fn start(&mut self, server: NodeId, name: &str, text: &str) {
+        let missing = parse_references(text)
+            .into_iter()
+            .filter(|reference| reference.kind == ReferenceKind::Media)
+            .map(|reference| reference.name)
+            .collect();
+
+        self.pending.push(PendingDocument {
+            server,
+            name: name.to_string(),
+            text: text.to_string(),
+            missing,
+            media: HashMap::new(),
+        });
+    }
+
+    /// Records the outcome of fetching the media `key`: its content, or `None` if it is unavailable.
+    /// Returns the documents that are now complete
+    pub fn resolve_media(&mut self, key: &MediaKey, data: Option<&Arc<[u8]>>) -> Vec<PendingDocument> {
+        for document in &mut self.pending {
+            if document.server == key.server && document.missing.remove(&key.name) {
+                document.media.insert(key.name.clone(), data.cloned());
+            }
+        }
+        self.take_complete()
+    }
+
+    /// Returns the documents that are not waiting for any media
+    pub fn take_complete(&mut self) -> Vec<PendingDocument> {
+        let (complete, pending) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|document| document.missing.is_empty());
+        self.pending = pending;
+        complete
+    }
+
+    /// Renders `document` and writes it to the output directory. Returns the path of the written file
+    /// # Errors
+    /// Returns an error if the document or its media cannot be written
+    pub fn render(&self, document: &PendingDocument) -> std::io::Result<PathBuf> {
+        let output_dir = &self.config.output_dir;
+        std::fs::create_dir_all(output_dir)?;
+
+        let mut body = String::new();
+        for segment in tokenize(&document.text) {
+            match segment {
+                Segment::Text(text) => match self.config.format {
+                    DocumentFormat::Html => body.push_str(&escape_html(text)),
+                    DocumentFormat::Markdown => body.push_str(text),
+                },
+                Segment::Reference(reference) => {
+                    let rendered = match reference.kind {
+                        ReferenceKind::Media => self.render_media(document, &reference)?,
+                        ReferenceKind::Text => self.render_link(document.server, &reference),
+                    };
+                    body.push_str(&rendered);
+                }
+            }
+        }
+
+        let content = match self.config.format {
+            DocumentFormat::Html => format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<div style=\"white-space: pre-wrap\">{body}</div>\n</body>\n</html>\n",
+                escape_html(&document.name)
+            ),
+            DocumentFormat::Markdown => body,
+        };
+
+        let path = output_dir.join(document_file_name(document.server, &document.name, self.config.format));
+        std::fs::write(&path, content)?;
+        Ok(path)
+    }
+
+    fn render_media(&self, document: &PendingDocument, reference: &Reference) -> std::io::Result<String> {
+        let alt = reference.alt.as_deref().unwrap_or(&reference.name);
+
+        let Some(Some(data)) = document.media.get(&reference.name) else {
+            let missing = format!("[missing media: {alt}]");
+            return Ok(match self.config.format {
+                DocumentFormat::Html => format!("<em>{}</em>", escape_html(&missing)),
+                DocumentFormat::Markdown => format!("*{}*", escape_markdown(&missing)),
+            });
+        };
+
+        let source = match self.config.embedding {
+            MediaEmbedding::Base64 => {
+                let mime = image::guess_format(data)
+                    .map_or("application/octet-stream", |format| format.to_mime_type());
+                format!("data:{mime};base64,{}", encode_base64(data))
+            }
+            MediaEmbedding::LocalFile => {
+                let media_dir = self.config.output_dir.join("media");
+                let path = write_content_addressed(&media_dir, &reference.name, data)?;
+                let file_name = path
+                    .file_name()
+                    .map(|file_name| file_name.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                format!("media/{file_name}")
+            }
+        };
+
+        Ok(match self.config.format {
+            DocumentFormat::Html => format!("<img src=\"{source}\" alt=\"{}\">", escape_html(alt)),
+            DocumentFormat::Markdown => {
+                format!("![{}]({})", escape_markdown(alt), escape_markdown_url(&source))
+            }
+        })
+    }
+
+    /// Links the document `reference`, which is requested to the `server` of the referencing one
+    fn render_link(&self, server: NodeId, reference: &Reference) -> String {
+        let label = reference.alt.as_deref().unwrap_or(&reference.name);
+        let target = document_file_name(server, &reference.name, self.config.format);
+        match self.config.format {
+            DocumentFormat::Html => {
+                format!("<a href=\"{}\">{}</a>", escape_html(&target), escape_html(label))
+            }
+            DocumentFormat::Markdown => {
+                format!("[{}]({})", escape_markdown(label), escape_markdown_url(&target))
+            }
+        }
+    }
+}
+
+/// Returns the file name of the rendered document `name` of `server`, without path separators
+#[must_use]
+pub fn document_file_name(server: NodeId, name: &str, format: DocumentFormat) -> String {
+    format!("{}.{}", unique_stem(server, name), format.extension())
+}
+
+/// Returns a file name stem for the file `name` of `server`, without path separators.
+/// The file name of `name`, extension included, is followed by a short hash of the whole `name`,
+/// so that the names differing only in their path or in the replaced characters do not collide
+pub(crate) fn unique_stem(server: NodeId, name: &str) -> String {
+    let file_name = Path::new(name)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem: String = file_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect();
+    let stem = if stem.is_empty() { "document".to_string() } else { stem };
+    let hash = content_hash(name.as_bytes()) >> 32;
+    format!("{server}_{stem}_{hash:08x}")
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes the characters that would end a Markdown link text early or start an emphasis
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '[' | ']' | '(' | ')' | '*' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Percent-encodes the characters that would end a Markdown link destination early
+fn escape_markdown_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        match c {
+            '(' => escaped.push_str("%28"),
+            ')' => escaped.push_str("%29"),
+            ' ' => escaped.push_str("%20"),
+            '<' => escaped.push_str("%3C"),
+            '>' => escaped.push_str("%3E"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = (u32::from(bytes[0]) << 16) | (u32::from(bytes[1]) << 8) | u32::from(bytes[2]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_base64_with_padding() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foo"), "Zm9v");
+        assert_eq!(encode_base64(b"foob"), "Zm9vYg==");
+        assert_eq!(encode_base64(b"fooba"), "Zm9vYmE=");
+        assert_eq!(encode_base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(encode_base64(&[0xff, 0xfe, 0x00]), "//4A");
+    }
+
+    #[test]
+    fn document_file_names_do_not_collide() {
+        let names = [
+            document_file_name(1, "intro.txt", DocumentFormat::Html),
+            document_file_name(2, "intro.txt", DocumentFormat::Html),
+            document_file_name(1, "intro.md", DocumentFormat::Html),
+            document_file_name(1, "docs/intro.txt", DocumentFormat::Html),
+            document_file_name(1, "intro txt", DocumentFormat::Html),
+        ];
+        let unique: HashSet<&String> = names.iter().collect();
+        assert_eq!(unique.len(), names.len(), "{names:?}");
+    }
+
+    #[test]
+    fn document_file_names_are_stable_and_safe() {
+        let name = document_file_name(3, "../notes/a b.txt", DocumentFormat::Markdown);
+        assert_eq!(name, document_file_name(3, "../notes/a b.txt", DocumentFormat::Markdown));
+        assert!(name.starts_with("3_a_b_txt_"), "{name}");
+        assert!(name.ends_with(".md"), "{name}");
+        assert!(!name.contains('/'), "{name}");
+
+        let empty = document_file_name(3, "", DocumentFormat::Html);
+        assert!(empty.starts_with("3_document_"), "{empty}");
+    }
+
+    #[test]
+    fn escapes_markdown_link_text_and_destination() {
+        assert_eq!(escape_markdown("a] (b) [c"), r"a\] \(b\) \[c");
+        assert_eq!(escape_markdown(r"x\y"), r"x\\y");
+        assert_eq!(escape_markdown_url("media/a (1).png"), "media/a%20%281%29.png");
+
+        let assembler = DocumentAssembler::new(DocumentConfig {
+            output_dir: PathBuf::new(),
+            format: DocumentFormat::Markdown,
+            embedding: MediaEmbedding::LocalFile,
+        });
+        let reference = parse_references("{{ next.txt | see ](here) }}").remove(0);
+        let link = assembler.render_link(1, &reference);
+        assert!(link.starts_with(r"[see \]\(here\)]("), "{link}");
+        assert_eq!(link.matches(')').count(), 2, "{link}");
+        assert!(link.ends_with(".md)"), "{link}");
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt::{Display, Formatter};
+use wg_2024::network::NodeId;
+
+/// Failure of a `DibClient` or of one of its components
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    /// The channel towards the named component is disconnected
+    Disconnected(&'static str),
+    /// A thread panicked while holding the lock of the named component
+    LockPoisoned(String),
+    /// The named thread cannot be spawned
+    Spawn { thread: String, reason: String },
+    /// The named thread panicked
+    ThreadPanicked(String),
+    /// The named thread ended without being asked to
+    Stopped(String),
+    /// The logic of a node ended with the described error
+    Logic(String),
+    /// The components of a node have been created with different node IDs
+    NodeIdMismatch { expected: NodeId, found: NodeId },
+}
+
+impl Display for ClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClientError::Disconnected(component) => {
+                write!(f, "the channel towards the {component} is disconnected")
+            }
+            ClientError::LockPoisoned(component) => write!(f, "the lock of the {component} is poisoned"),
+            ClientError::Spawn { thread, reason } => {
+                write!(f, "cannot spawn thread '{thread}': {reason}")
+            }
+            ClientError::ThreadPanicked(thread) => write!(f, "thread '{thread}' panicked"),
+            ClientError::Stopped(thread) => write!(f, "thread '{thread}' stopped unexpectedly"),
+            ClientError::Logic(reason) => write!(f, "the logic failed: {reason}"),
+            ClientError::NodeIdMismatch { expected, found } => {
+                write!(f, "component created for node {found} instead of node {expected}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
@@ -0,0 +1,89 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use messages::{RequestType, ServerType};
+use wg_2024::network::NodeId;
+use crate::crawl::CrawlStats;
+use crate::error::ClientError;
+use crate::media::MediaError;
+use crate::report::ScenarioReport;
+use crate::runtime::{Component, NodeState};
+
+/// Notable facts reported by the client on `ClientConfig::event_tx`
+#[derive(Debug, Clone)]
+pub enum ClientEvent {
+    /// A request never received a response, even after all the retries
+    RequestFailed(RequestFailure),
+    /// A valid media has been received. `served_by` differs from `requested_from` if the
+    /// media has been obtained from another server, following the `MediaFallbackPolicy`
+    MediaFetched {
+        name: String,
+        requested_from: NodeId,
+        served_by: NodeId,
+    },
+    /// A received media failed validation and has been dropped
+    MediaRejected {
+        name: String,
+        source: NodeId,
+        error: MediaError,
+        /// Where the payload has been saved, if a quarantine directory is configured
+        quarantined: Option<PathBuf>,
+    },
+    /// A received text has been rendered with its media
+    DocumentRendered {
+        name: String,
+        source: NodeId,
+        path: PathBuf,
+    },
+    /// Every text and media reachable from the text list of `server` has been fetched or given up
+    CrawlFinished {
+        server: NodeId,
+        stats: CrawlStats,
+    },
+    /// A server answered a `DiscoveryRequest` for the first time, or with a different type
+    ServerDiscovered {
+        server: NodeId,
+        server_type: ServerType,
+    },
+    /// A chat server sent its list of registered clients
+    ClientListReceived {
+        server: NodeId,
+        clients: Vec<NodeId>,
+    },
+    /// A message from another client has been pushed by a chat server
+    MessageReceived {
+        server: NodeId,
+        from: NodeId,
+        message: String,
+    },
+    /// The scenario has completed, or the client stopped while running it
+    ScenarioFinished(ScenarioReport),
+    /// Something went wrong, but the client keeps running as far as it can
+    Error(ClientError),
+    /// The node entered a new lifecycle state
+    NodeStateChanged(NodeState),
+    /// The thread of `component` panicked. `location` is where, in the source code, if known
+    Panicked {
+        node_id: NodeId,
+        component: Component,
+        message: String,
+        location: Option<String>,
+    },
+    /// The thread of `component` ended abnormally. If it has not been `restarted`, the node has failed
+    ComponentCrashed {
+        component: Component,
+        error: ClientError,
+        restarted: bool,
+    },
+}
+
+/// Record of a request given up after exhausting its retries
+#[derive(Debug, Clone)]
+pub struct RequestFailure {
+    /// Session used by the last attempt
+    pub session_id: u64,
+    pub destination: NodeId,
+    pub request: RequestType,
+    pub attempts: u32,
+    /// Time elapsed since the first attempt was sent
+    pub elapsed: Duration,
+}
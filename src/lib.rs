@@ -3,378 +3,274 @@
 #![allow(clippy::module_name_repetitions)]
 
 use std::collections::HashMap;
-use std::{panic, thread};
-use std::sync::{Arc, Mutex};
 use std::time::Duration;
-use ap_listener::{Command as ListenerCommand, Listener};
-use ap_sc_notifier::SimulationControllerNotifier;
-use ap_transmitter::{Command as TransmitterCommand, Transmitter};
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use messages::RequestType;
 use messages::node_event::NodeEvent;
 use wg_2024::controller::DroneCommand;
 use wg_2024::network::NodeId;
 use wg_2024::packet::{NodeType, Packet};
-use crate::client::Client;
-use crate::logic::{ClientCommand, ClientLogic, Getter};
+
+pub use crate::chat::{
+    ChatMessage, ChatServerState, ChatState, ConversationEntry, DeliveryStats, DeliveryStatus,
+    OutgoingMessage,
+};
+pub use crate::client::Client;
+pub use crate::config::{ClientConfig, PipelineLimits, RetryPolicy};
+pub use crate::crawl::{CrawlConfig, CrawlStats};
+pub use crate::directory::{DiscoveryConfig, ServerDirectory, ServerEntry};
+pub use crate::document::{DocumentConfig, DocumentFormat, MediaEmbedding};
+pub use crate::error::ClientError;
+pub use crate::event::{ClientEvent, RequestFailure};
+pub use crate::logic::{ClientCommand, ClientLogic, Getter, LogicChannels};
+pub use crate::markup::{parse_references, tokenize, Reference, ReferenceKind, Segment};
+pub use crate::media::{validate_media, MediaError, MediaInfo, MediaLimits};
+pub use crate::media_fallback::MediaFallbackPolicy;
+pub use crate::media_sink::{DirectorySink, DiscardSink, MediaSink, MemorySink, ViewerSink};
+pub use crate::report::{Expectation, ExpectationResult, ScenarioReport};
+pub use crate::response::ResponseKind;
+pub use crate::routing::RoutingStrategy;
+pub use crate::runtime::{
+    Component, NodeCommand, NodeLogic, NodeRuntime, NodeState, NodeStatus, RestartPolicy,
+};
+pub use crate::scenario::{Condition, Scenario, ScenarioError};
+pub use crate::state::{ClientState, ScenarioProgress};
 
 mod logic;
+mod chat;
 mod client;
+mod config;
+mod crawl;
+mod directory;
+mod document;
+mod error;
+mod event;
+mod markup;
+mod media;
+mod media_cache;
+mod media_fallback;
+mod media_sink;
+mod panic_hook;
+mod report;
+mod response;
+mod routing;
+mod runner;
+mod runtime;
+mod scenario;
+mod session;
+mod state;
 
 pub enum Command {
     Quit,
+    /// Sends `request` to `destination`, independently of the scenario
+    SendRequest {
+        destination: NodeId,
+        request: RequestType,
+    },
+    /// Stops sending the scenario requests. The requests already sent are still followed
+    Pause,
+    /// Resumes the scenario after `Command::Pause`
+    Resume,
+    /// Waits the given time after every scenario request, instead of the delays of the scenario
+    SetSleepTime(Duration),
+    /// Ends the running scenario, reporting it, and starts the given one
+    ReplaceScenario(Scenario),
+    /// Answers with a snapshot of the client on the given channel
+    QueryState(Sender<ClientState>),
 }
 
-pub struct DibClient {
-    node_id: NodeId,
-    listener: Arc<Mutex<Listener>>,
-    listener_command_tx: Sender<ListenerCommand>,
-    logic: Arc<Mutex<Client>>,
-    logic_command_tx: Sender<ClientCommand>,
-    transmitter: Arc<Mutex<Transmitter>>,
-    transmitter_command_tx: Sender<TransmitterCommand>,
+/// A client node: a `Listener` and a `Transmitter` wired to the `ClientLogic` `L`
+pub struct DibNode<L: ClientLogic + 'static> {
+    runtime: NodeRuntime<L>,
     command_rx: Receiver<Command>,
 }
 
-impl DibClient {
-    #[must_use]
-    pub fn new_dib_client(
+impl<L: ClientLogic + 'static> DibNode<L> {
+    /// Creates a `DibNode` whose logic is built by `build_logic`, out of the channels
+    /// connecting it to the listener and to the transmitter of the node
+    /// # Errors
+    /// Returns an error if the components of the node do not agree on its node ID
+    pub fn with_logic<F: FnOnce(LogicChannels) -> L>(
         node_id: NodeId,
         listener_rx: Receiver<Packet>,
         drones_tx: HashMap<NodeId, Sender<Packet>>,
         simulation_controller_tx: Sender<NodeEvent>,
         drone_command_rx: Receiver<DroneCommand>,
-        requests: Vec<(NodeId, RequestType)>,
-        sleep_time: Duration,
-    ) -> (Self, Sender<Command>) {
-        let (listener_to_transmitter_tx, listener_to_transmitter_rx) = unbounded();
-        let (listener_to_server_logic_tx, listener_to_server_logic_rx) = unbounded();
-        let (logic_to_transmitter_tx, logic_to_transmitter_rx) = unbounded();
-        let (listener_command_tx, listener_command_rx) = unbounded();
-
-        let simulation_controller_notifier =
-            SimulationControllerNotifier::new(simulation_controller_tx);
-        let simulation_controller_notifier = Arc::new(simulation_controller_notifier);
-
-        let (transmitter_command_tx, transmitter_command_rx) = unbounded();
-
-        let transmitter = Transmitter::new(
+        build_logic: F,
+    ) -> Result<(Self, Sender<Command>), ClientError> {
+        let runtime = NodeRuntime::new(
             node_id,
             NodeType::Client,
-            listener_to_transmitter_rx,
-            logic_to_transmitter_rx,
+            listener_rx,
             drones_tx,
-            simulation_controller_notifier.clone(),
-            transmitter_command_rx,
-            Duration::from_secs(60),
+            simulation_controller_tx,
             drone_command_rx,
-        );
-
-        let listener = Listener::new(
-            node_id,
-            listener_to_transmitter_tx,
-            listener_to_server_logic_tx,
-            listener_rx,
-            listener_command_rx,
-            simulation_controller_notifier.clone(),
-        );
-
-        let (logic_command_tx, logic_command_rx) = unbounded();
-
-        let logic = Client::new(
-            node_id,
-            logic_to_transmitter_tx,
-            listener_to_server_logic_rx,
-            logic_command_rx,
-            requests,
-            sleep_time,
-        );
-
-        assert_eq!(transmitter.get_node_id(), listener.get_node_id());
-        assert_eq!(transmitter.get_node_id(), logic.get_node_id());
-
-        let transmitter = Arc::new(Mutex::new(transmitter));
-        let listener = Arc::new(Mutex::new(listener));
-        let logic = Arc::new(Mutex::new(logic));
-
+            build_logic,
+        )?;
         let (command_tx, command_rx) = unbounded();
 
-        let result = Self {
-            node_id,
-            listener,
-            listener_command_tx,
-            logic,
-            logic_command_tx,
-            transmitter,
-            transmitter_command_tx,
-            command_rx,
-        };
-
-        (result, command_tx)
+        Ok((Self { runtime, command_rx }, command_tx))
     }
 
-    pub fn run(&mut self) {
-        panic::set_hook(Box::new(|info| {
-            let panic_msg = format!("Panic occurred: {info}");
-            log::error!("{panic_msg}");
-            eprintln!("{panic_msg}");
-        }));
-
-        let listener = self.get_listener().clone();
-
-        let listener_handle = thread::Builder::new()
-            .name(format!("server_{}_listener", self.get_node_id()))
-            .spawn(move || {
-                let mut listener = match listener.lock() {
-                    Ok(listener) => listener,
-                    Err(err) => {
-                        panic!("Error while starting listener: {err:?}");
-                    }
-                };
-                listener.run();
-            })
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Cannot spawn a new thread 'server_{}_listener'",
-                    self.get_node_id()
-                )
-            });
-
-        let transmitter = self.get_transmitter().clone();
-        let transmitter_handle = thread::Builder::new()
-            .name(format!("server_{}_transmitter", self.get_node_id()))
-            .spawn(move || {
-                let mut transmitter = match transmitter.lock() {
-                    Ok(transmitter) => transmitter,
-                    Err(err) => {
-                        panic!("Error while starting transmitter: {err:?}");
-                    }
-                };
-                transmitter.run();
-            })
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Cannot spawn a new thread 'server_{}_transmitter'",
-                    self.get_node_id()
-                )
-            });
-
-        let logic = self.get_logic().clone();
-        let server_logic_handle = thread::Builder::new()
-            .name(format!("server_{}_logic", self.get_node_id()))
-            .spawn(move || {
-                let mut logic = match logic.lock() {
-                    Ok(logic) => logic,
-                    Err(err) => {
-                        panic!("Error while starting logic: {err:?}");
-                    }
-                };
-                logic.run();
-            })
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Cannot spawn a new thread 'server_{}_logic'",
-                    self.get_node_id()
-                )
-            });
+    /// Reports the errors, the panics and the state changes of the node on `event_tx`
+    pub fn set_event_tx(&mut self, event_tx: Sender<ClientEvent>) {
+        self.runtime.set_event_tx(event_tx);
+    }
 
-        #[allow(clippy::never_loop)]
-        'command_loop: loop {
-            let command = self.get_command_rx().recv();
-            match command {
-                Ok(command) => match command {
-                    Command::Quit => {
-                        let command = ListenerCommand::Quit;
-                        self.get_listener_tx()
-                            .send(command)
-                            .unwrap_or_else(|_| panic!("Cannot communicate with listener thread"));
+    /// Sets how the logic thread is restarted after a crash
+    pub fn set_restart_policy(&mut self, restart_policy: RestartPolicy) {
+        self.runtime.set_restart_policy(restart_policy);
+    }
 
-                        let command = ClientCommand::Quit;
-                        self.get_logic_tx()
-                            .send(command)
-                            .unwrap_or_else(|_| panic!("Cannot communicate with logic thread"));
+    /// Returns a handle to the lifecycle state of the node
+    #[must_use]
+    pub fn status(&self) -> NodeStatus {
+        self.runtime.status()
+    }
 
-                        let command = TransmitterCommand::Quit;
-                        self.get_transmitter_tx().send(command).unwrap_or_else(|_| {
-                            panic!("Cannot communicate with transmitter thread")
-                        });
+    /// Starts the node and handles its `Command`s until `Command::Quit`
+    /// # Errors
+    /// Returns an error if a thread cannot be started, if the command channel gets disconnected
+    /// or if a thread ends with an error
+    pub fn run(&mut self) -> Result<(), ClientError> {
+        self.runtime.start()?;
+        let result = self.handle_commands();
+        let stopped = self.runtime.stop();
+        result.and(stopped)
+    }
 
-                        break 'command_loop;
-                    }
-                },
+    /// Forwards the received `Command`s to the logic until `Command::Quit`, while the runtime
+    /// supervises the threads
+    fn handle_commands(&mut self) -> Result<(), ClientError> {
+        loop {
+            let command = match self.runtime.next_command(&self.command_rx) {
+                Ok(Command::Quit) => return Ok(()),
+                Ok(Command::SendRequest { destination, request }) => {
+                    ClientCommand::SendRequest { destination, request }
+                }
+                Ok(Command::Pause) => ClientCommand::Pause,
+                Ok(Command::Resume) => ClientCommand::Resume,
+                Ok(Command::SetSleepTime(sleep_time)) => ClientCommand::SetSleepTime(sleep_time),
+                Ok(Command::ReplaceScenario(scenario)) => ClientCommand::ReplaceScenario(scenario),
+                Ok(Command::QueryState(reply_tx)) => ClientCommand::QueryState(reply_tx),
                 Err(error) => {
-                    let error = format!("Error while receiving Command's. Error: {error:?}");
-                    log::error!("{error}");
-                    panic!("{error}");
+                    log::error!("Node {}: {error}, stopping", self.runtime.node_id());
+                    return Err(error);
                 }
+            };
+            if let Err(error) = self.runtime.send_to_logic(command) {
+                self.runtime.report_error(error);
             }
         }
-
-        let _ = listener_handle.join();
-        let _ = server_logic_handle.join();
-        let _ = transmitter_handle.join();
     }
 }
 
-pub trait DibGetter {
-    fn get_node_id(&self) -> NodeId;
-
-    fn get_listener(&self) -> Arc<Mutex<Listener>>;
-
-    fn get_listener_tx(&self) -> &Sender<ListenerCommand>;
-
-    fn get_logic(&self) -> Arc<Mutex<Client>>;
-
-    fn get_logic_tx(&self) -> &Sender<ClientCommand>;
-
-    fn get_transmitter(&self) -> Arc<Mutex<Transmitter>>;
-
-    fn get_transmitter_tx(&self) -> &Sender<TransmitterCommand>;
-
-    fn get_command_rx(&self) -> &Receiver<Command>;
+/// A `DibNode` running the `Client` logic, with handles to the state it shares
+pub struct DibClient {
+    node: DibNode<Client>,
+    chat_state: ChatState,
+    server_directory: ServerDirectory,
 }
 
-impl DibGetter for DibClient {
-    fn get_node_id(&self) -> NodeId {
-        self.node_id
-    }
-
-    fn get_listener(&self) -> Arc<Mutex<Listener>> {
-        self.listener.clone()
+impl DibClient {
+    /// Creates a `DibClient` running `requests`, one every `sleep_time`
+    /// # Errors
+    /// Returns an error if the components of the client do not agree on its node ID
+    pub fn new_dib_client(
+        node_id: NodeId,
+        listener_rx: Receiver<Packet>,
+        drones_tx: HashMap<NodeId, Sender<Packet>>,
+        simulation_controller_tx: Sender<NodeEvent>,
+        drone_command_rx: Receiver<DroneCommand>,
+        requests: Vec<(NodeId, RequestType)>,
+        sleep_time: Duration,
+    ) -> Result<(Self, Sender<Command>), ClientError> {
+        Self::new_dib_client_with_config(
+            node_id,
+            listener_rx,
+            drones_tx,
+            simulation_controller_tx,
+            drone_command_rx,
+            Scenario::from_actions(requests, sleep_time),
+            ClientConfig::default(),
+        )
     }
 
-    fn get_listener_tx(&self) -> &Sender<ListenerCommand> {
-        &self.listener_command_tx
-    }
+    /// Creates a `DibClient` like `new_dib_client`, running `scenario` with custom settings
+    /// # Errors
+    /// Returns an error if the components of the client do not agree on its node ID
+    pub fn new_dib_client_with_config(
+        node_id: NodeId,
+        listener_rx: Receiver<Packet>,
+        drones_tx: HashMap<NodeId, Sender<Packet>>,
+        simulation_controller_tx: Sender<NodeEvent>,
+        drone_command_rx: Receiver<DroneCommand>,
+        scenario: Scenario,
+        config: ClientConfig,
+    ) -> Result<(Self, Sender<Command>), ClientError> {
+        let chat_state = config.chat_state.clone();
+        let server_directory = config.server_directory.clone();
+        let event_tx = config.event_tx.clone();
+        let restart_policy = config.restart_policy.clone();
+
+        let (mut node, command_tx) = DibNode::with_logic(
+            node_id,
+            listener_rx,
+            drones_tx,
+            simulation_controller_tx,
+            drone_command_rx,
+            |channels| {
+                Client::new(
+                    channels.node_id,
+                    channels.transmitter_tx,
+                    channels.listener_rx,
+                    channels.command_rx,
+                    scenario,
+                    config,
+                )
+            },
+        )?;
+        node.set_restart_policy(restart_policy);
+        if let Some(event_tx) = event_tx {
+            node.set_event_tx(event_tx);
+        }
 
-    fn get_logic(&self) -> Arc<Mutex<Client>> {
-        self.logic.clone()
+        let client = Self {
+            node,
+            chat_state,
+            server_directory,
+        };
+        Ok((client, command_tx))
     }
 
-    fn get_logic_tx(&self) -> &Sender<ClientCommand> {
-        &self.logic_command_tx
+    /// Returns a handle to the chat state of the client, which can be queried while the client runs
+    #[must_use]
+    pub fn chat_state(&self) -> ChatState {
+        self.chat_state.clone()
     }
 
-    fn get_transmitter(&self) -> Arc<Mutex<Transmitter>> {
-        self.transmitter.clone()
+    /// Returns a handle to the directory of the servers discovered by the client
+    #[must_use]
+    pub fn server_directory(&self) -> ServerDirectory {
+        self.server_directory.clone()
     }
 
-    fn get_transmitter_tx(&self) -> &Sender<TransmitterCommand> {
-        &self.transmitter_command_tx
+    /// Reports the errors, the panics and the state changes of the client on `event_tx`
+    pub fn set_event_tx(&mut self, event_tx: Sender<ClientEvent>) {
+        self.node.set_event_tx(event_tx);
     }
 
-    fn get_command_rx(&self) -> &Receiver<Command> {
-        &self.command_rx
+    /// Returns a handle to the lifecycle state of the client
+    #[must_use]
+    pub fn status(&self) -> NodeStatus {
+        self.node.status()
     }
-}
-
-pub trait DibServerTrait: DibGetter {
-    /// Starts the client
-    /// # Panics
-    /// - Panics if the transmitter thread cannot acquire the lock on the transmitter
-    /// - Panics if the listener thread cannot acquire the lock on the listener
-    /// - Panics if the server logic thread cannot acquire the lock on the server logic
-    fn run(&mut self) {
-        panic::set_hook(Box::new(|info| {
-            let panic_msg = format!("Panic occurred: {info}");
-            log::error!("{panic_msg}");
-            eprintln!("{panic_msg}");
-        }));
-
-        let listener = self.get_listener().clone();
-
-        let listener_handle = thread::Builder::new()
-            .name(format!("client_{}_listener", self.get_node_id()))
-            .spawn(move || {
-                let mut listener = match listener.lock() {
-                    Ok(listener) => listener,
-                    Err(err) => {
-                        panic!("Error while starting listener: {err:?}");
-                    }
-                };
-                listener.run();
-            })
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Cannot spawn a new thread 'client_{}_listener'",
-                    self.get_node_id()
-                )
-            });
-
-        let transmitter = self.get_transmitter().clone();
-        let transmitter_handle = thread::Builder::new()
-            .name(format!("client_{}_transmitter", self.get_node_id()))
-            .spawn(move || {
-                let mut transmitter = match transmitter.lock() {
-                    Ok(transmitter) => transmitter,
-                    Err(err) => {
-                        panic!("Error while starting transmitter: {err:?}");
-                    }
-                };
-                transmitter.run();
-            })
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Cannot spawn a new thread 'client_{}_transmitter'",
-                    self.get_node_id()
-                )
-            });
 
-        let logic = self.get_logic().clone();
-        let client_logic_handle = thread::Builder::new()
-            .name(format!("client_{}_logic", self.get_node_id()))
-            .spawn(move || {
-                let mut logic = match logic.lock() {
-                    Ok(logic) => logic,
-                    Err(err) => {
-                        panic!("Error while starting logic: {err:?}");
-                    }
-                };
-                logic.run();
-            })
-            .unwrap_or_else(|_| {
-                panic!(
-                    "Cannot spawn a new thread 'client_{}_logic'",
-                    self.get_node_id()
-                )
-            });
-
-        #[allow(clippy::never_loop)]
-        'command_loop: loop {
-            let command = self.get_command_rx().recv();
-            match command {
-                Ok(command) => match command {
-                    Command::Quit => {
-                        let command = ListenerCommand::Quit;
-                        self.get_listener_tx()
-                            .send(command)
-                            .unwrap_or_else(|_| panic!("Cannot communicate with listener thread"));
-
-                        let command = ClientCommand::Quit;
-                        self.get_logic_tx()
-                            .send(command)
-                            .unwrap_or_else(|_| panic!("Cannot communicate with logic thread"));
-
-                        let command = TransmitterCommand::Quit;
-                        self.get_transmitter_tx().send(command).unwrap_or_else(|_| {
-                            panic!("Cannot communicate with transmitter thread")
-                        });
-
-                        break 'command_loop;
-                    }
-                },
-                Err(error) => {
-                    let error = format!("Error while receiving Command's. Error: {error:?}");
-                    log::error!("{error}");
-                    panic!("{error}");
-                }
-            }
-        }
-
-        let _ = listener_handle.join();
-        let _ = client_logic_handle.join();
-        let _ = transmitter_handle.join();
+    /// Starts the client and handles its `Command`s until `Command::Quit`
+    /// # Errors
+    /// Returns an error if a thread cannot be started, if the command channel gets disconnected
+    /// or if a thread ends with an error
+    pub fn run(&mut self) -> Result<(), ClientError> {
+        self.node.run()
     }
 }
-
-impl DibServerTrait for DibClient {}
\ No newline at end of file
@@ -1,10 +1,45 @@
+use std::time::Duration;
 use crossbeam_channel::{Receiver, Sender};
 use messages::{ErrorType, Message, MessageType, RequestType, ResponseType};
 use wg_2024::network::NodeId;
+use crate::error::ClientError;
+use crate::runtime::{NodeCommand, NodeLogic};
+use crate::scenario::Scenario;
+use crate::state::ClientState;
 
 #[derive(Debug)]
 pub enum ClientCommand {
-    Quit
+    Quit,
+    /// Sends `request` to `destination` outside of the scenario
+    SendRequest {
+        destination: NodeId,
+        request: RequestType,
+    },
+    /// Stops sending the scenario requests. The requests already sent are still followed
+    Pause,
+    Resume,
+    /// Waits the given time after every scenario request, instead of the delays of the scenario
+    SetSleepTime(Duration),
+    /// Ends the running scenario, reporting it, and starts the given one
+    ReplaceScenario(Scenario),
+    /// Answers with a snapshot of the client on the given channel
+    QueryState(Sender<ClientState>),
+}
+
+impl NodeCommand for ClientCommand {
+    fn quit() -> Self {
+        ClientCommand::Quit
+    }
+}
+
+/// Channels connecting the logic of a node to its other components
+pub struct LogicChannels<C = ClientCommand> {
+    pub node_id: NodeId,
+    /// Messages to be sent by the transmitter
+    pub transmitter_tx: Sender<Message>,
+    /// Messages received by the listener
+    pub listener_rx: Receiver<Message>,
+    pub command_rx: Receiver<C>,
 }
 
 pub trait Getter {
@@ -16,7 +51,9 @@ pub trait Getter {
 
 pub trait ClientLogic: Getter + Send {
     /// Starts the server, making it able to receive and send `Message`s
-    fn run(&mut self);
+    /// # Errors
+    /// Returns an error if the logic cannot keep running
+    fn run(&mut self) -> Result<(), ClientError>;
 
     /// Processes a received `Message`
     fn process_message(&mut self, message: &Message) {
@@ -42,14 +79,16 @@ pub trait ClientLogic: Getter + Send {
     fn process_request(&mut self, session_id: u64, source: NodeId, request_type: &RequestType) {
         let content = MessageType::Error(ErrorType::Unsupported(request_type.clone()));
         let response = self.create_message(session_id, source, content);
-        self.send_message_to_transmitter(response);
+        if let Err(error) = self.send_message_to_transmitter(response) {
+            log::error!("Cannot answer node {source} with session_id {session_id}: {error}");
+        }
     }
 
     /// Processes a received `ResponseType`
     fn process_response(&mut self, session_id: u64, source_id: NodeId, response_type: &ResponseType);
 
     /// Processes a received `ErrorType`. There is not much to do, so the error just gets logged and then ignored
-    fn process_error(&self, session_id: u64, source_id: NodeId, error_type: &ErrorType) {
+    fn process_error(&mut self, session_id: u64, source_id: NodeId, error_type: &ErrorType) {
         log::warn!(
             "From node {source_id} with session_id {session_id}, received error {error_type:?}"
         );
@@ -71,14 +110,24 @@ pub trait ClientLogic: Getter + Send {
     }
 
     /// Sends a `Message` to `Transmitter`
-    /// # Panics
-    /// Panics if the communication fails
-    fn send_message_to_transmitter(&self, message: Message) {
-        match self.get_server_logic_to_transmitter_tx().send(message) {
-            Ok(()) => {}
-            Err(error) => {
-                core::panic!("Logic cannot communicate with transmitter. Error: {error:?}");
-            }
-        }
+    /// # Errors
+    /// Returns an error if the transmitter is disconnected
+    fn send_message_to_transmitter(&self, message: Message) -> Result<(), ClientError> {
+        self.get_server_logic_to_transmitter_tx()
+            .send(message)
+            .map_err(|_| ClientError::Disconnected("transmitter"))
+    }
+}
+
+impl<L: ClientLogic> NodeLogic for L {
+    type Command = ClientCommand;
+    type Error = ClientError;
+
+    fn node_id(&self) -> NodeId {
+        self.get_node_id()
+    }
+
+    fn run_logic(&mut self) -> Result<(), ClientError> {
+        ClientLogic::run(self)
     }
-}
\ No newline at end of file
+}
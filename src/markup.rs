@@ -0,0 +1,228 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+/// Extensions of the references resolved with a `MediaRequest`
+const MEDIA_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "bmp", "webp", "ico"];
+
+/// Extensions of the references resolved with a `TextRequest`
+const TEXT_EXTENSIONS: [&str; 5] = ["txt", "md", "markdown", "html", "htm"];
+
+/// What a reference points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReferenceKind {
+    Media,
+    Text,
+}
+
+/// A `{{ name }}` tag found in a text. The name can be quoted to contain spaces and can
+/// be followed by an alternative text: `{{ "my logo.png" | The company logo }}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reference {
+    pub kind: ReferenceKind,
+    pub name: String,
+    pub alt: Option<String>,
+}
+
+/// A piece of a parsed text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment<'a> {
+    /// Text to keep as is, including malformed tags
+    Text(&'a str),
+    Reference(Reference),
+}
+
+/// Splits `text` into plain text and references, in order of appearance
+#[must_use]
+pub fn tokenize(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut literal_start = 0;
+    let mut cursor = 0;
+
+    while let Some(offset) = text[cursor..].find("{{") {
+        let open = cursor + offset;
+        match parse_tag(text, open) {
+            Some((reference, end)) => {
+                if literal_start < open {
+                    segments.push(Segment::Text(&text[literal_start..open]));
+                }
+                segments.push(Segment::Reference(reference));
+                literal_start = end;
+                cursor = end;
+            }
+            None => cursor = open + 1,
+        }
+    }
+
+    if literal_start < text.len() {
+        segments.push(Segment::Text(&text[literal_start..]));
+    }
+    segments
+}
+
+/// Returns the references of `text` in order of first appearance, without duplicates
+#[must_use]
+pub fn parse_references(text: &str) -> Vec<Reference> {
+    let mut seen = HashSet::new();
+    tokenize(text)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Reference(reference) => Some(reference),
+            Segment::Text(_) => None,
+        })
+        .filter(|reference| seen.insert((reference.kind, reference.name.clone())))
+        .collect()
+}
+
+/// Parses the tag opening at `open`. Returns the reference and the index following the tag,
+/// or `None` if the tag is malformed or refers to an unsupported file
+fn parse_tag(text: &str, open: usize) -> Option<(Reference, usize)> {
+    let body_start = open + 2;
+    let rest = &text[body_start..];
+    let trimmed = rest.trim_start();
+    let name_start = body_start + (rest.len() - trimmed.len());
+
+    let (name, after_name) = match trimmed.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            let closing = trimmed[1..].find(quote)?;
+            let name = &trimmed[1..=closing];
+            (name, name_start + closing + 2)
+        }
+        _ => {
+            let end = trimmed
+                .find(|c: char| c.is_whitespace() || c == '|' || c == '}' || c == '{')
+                .unwrap_or(trimmed.len());
+            (&trimmed[..end], name_start + end)
+        }
+    };
+
+    let close = after_name + text[after_name..].find("}}")?;
+    let tail = text[after_name..close].trim();
+    if tail.contains("{{") {
+        return None;
+    }
+
+    let alt = match tail.strip_prefix('|') {
+        Some(alt) => Some(alt.trim()).filter(|alt| !alt.is_empty()),
+        None if tail.is_empty() => None,
+        None => return None,
+    };
+
+    let name = name.trim();
+    if name.is_empty() || name.contains(['{', '}']) {
+        return None;
+    }
+
+    let reference = Reference {
+        kind: kind_of(name)?,
+        name: name.to_string(),
+        alt: alt.map(ToString::to_string),
+    };
+    Some((reference, close + 2))
+}
+
+/// Classifies `name` by its extension
+fn kind_of(name: &str) -> Option<ReferenceKind> {
+    let extension = Path::new(name)
+        .extension()?
+        .to_str()?
+        .to_ascii_lowercase();
+
+    if MEDIA_EXTENSIONS.contains(&extension.as_str()) {
+        Some(ReferenceKind::Media)
+    } else if TEXT_EXTENSIONS.contains(&extension.as_str()) {
+        Some(ReferenceKind::Text)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn media(name: &str) -> Reference {
+        Reference {
+            kind: ReferenceKind::Media,
+            name: name.to_string(),
+            alt: None,
+        }
+    }
+
+    #[test]
+    fn parses_media_with_any_supported_extension() {
+        let text = "{{a.png}} {{ b.JPG }} {{c.jpeg}} {{d.gif}} {{e.bmp}} {{f.webp}}";
+        let names: Vec<String> = parse_references(text)
+            .into_iter()
+            .map(|reference| reference.name)
+            .collect();
+        assert_eq!(names, ["a.png", "b.JPG", "c.jpeg", "d.gif", "e.bmp", "f.webp"]);
+    }
+
+    #[test]
+    fn parses_quoted_names_and_alt_text() {
+        let references = parse_references(r#"{{ "my logo.png" | The logo }} {{ 'other one.gif' }}"#);
+        assert_eq!(
+            references,
+            [
+                Reference {
+                    kind: ReferenceKind::Media,
+                    name: "my logo.png".to_string(),
+                    alt: Some("The logo".to_string()),
+                },
+                media("other one.gif"),
+            ]
+        );
+    }
+
+    #[test]
+    fn recognises_text_links() {
+        let references = parse_references("See {{ next.txt | next page }} and {{notes.md}}");
+        assert_eq!(references.len(), 2);
+        assert!(references
+            .iter()
+            .all(|reference| reference.kind == ReferenceKind::Text));
+        assert_eq!(references[0].alt.as_deref(), Some("next page"));
+    }
+
+    #[test]
+    fn deduplicates_references() {
+        let references = parse_references("{{a.png}} {{ a.png | again }} {{b.png}} {{a.png}}");
+        assert_eq!(
+            references
+                .iter()
+                .map(|reference| reference.name.as_str())
+                .collect::<Vec<_>>(),
+            ["a.png", "b.png"]
+        );
+    }
+
+    #[test]
+    fn handles_nested_braces() {
+        assert_eq!(parse_references("{{ {{a.png}} }}"), [media("a.png")]);
+        assert_eq!(parse_references("{{{a.png}}}"), [media("a.png")]);
+        assert!(parse_references("{{ a{b}.png }}").is_empty());
+    }
+
+    #[test]
+    fn ignores_malformed_tags() {
+        assert!(parse_references("{{ a.png").is_empty());
+        assert!(parse_references("{{ a b.png }}").is_empty());
+        assert!(parse_references("{{ \"unterminated.png }}").is_empty());
+        assert!(parse_references("{{ archive.zip }}").is_empty());
+        assert!(parse_references("{{ }} {{ | alt }}").is_empty());
+        assert!(parse_references("{ a.png }").is_empty());
+    }
+
+    #[test]
+    fn tokenize_keeps_surrounding_text() {
+        let segments = tokenize("Hello {{a.png}}, {{ bad tag!");
+        assert_eq!(
+            segments,
+            [
+                Segment::Text("Hello "),
+                Segment::Reference(media("a.png")),
+                Segment::Text(", {{ bad tag!"),
+            ]
+        );
+    }
+}
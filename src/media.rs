@@ -0,0 +1,224 @@
+use std::fmt::{Display, Formatter};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use image::io::{Limits, Reader as ImageReader};
+use image::ImageFormat;
+use wg_2024::network::NodeId;
+use crate::media_sink::content_hash;
+
+/// Bounds applied to the received media before they are accepted
+#[derive(Debug, Clone)]
+pub struct MediaLimits {
+    /// Maximum size of the encoded payload
+    pub max_bytes: usize,
+    pub max_width: u32,
+    pub max_height: u32,
+    /// Maximum memory the decoder may allocate, to guard against decompression bombs
+    pub max_decoded_bytes: u64,
+}
+
+impl Default for MediaLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: 16 * 1024 * 1024,
+            max_width: 8192,
+            max_height: 8192,
+            max_decoded_bytes: 256 * 1024 * 1024,
+        }
+    }
+}
+
+/// Reason why a media payload has been rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaError {
+    Empty,
+    TooLarge { size: usize, max: usize },
+    UnknownFormat,
+    DimensionsExceeded { width: u32, height: u32 },
+    Decode(String),
+}
+
+impl Display for MediaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MediaError::Empty => write!(f, "empty payload"),
+            MediaError::TooLarge { size, max } => {
+                write!(f, "payload of {size} bytes exceeds the limit of {max} bytes")
+            }
+            MediaError::UnknownFormat => write!(f, "unknown image format"),
+            MediaError::DimensionsExceeded { width, height } => {
+                write!(f, "image of {width}x{height} pixels exceeds the limits")
+            }
+            MediaError::Decode(error) => write!(f, "cannot decode image: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for MediaError {}
+
+/// Properties of a validated media
+#[derive(Debug, Clone, Copy)]
+pub struct MediaInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Checks that `data` is an image within `limits` that can be fully decoded
+/// # Errors
+/// Returns the reason why `data` is not acceptable
+pub fn validate_media(data: &[u8], limits: &MediaLimits) -> Result<MediaInfo, MediaError> {
+    if data.is_empty() {
+        return Err(MediaError::Empty);
+    }
+    if data.len() > limits.max_bytes {
+        return Err(MediaError::TooLarge {
+            size: data.len(),
+            max: limits.max_bytes,
+        });
+    }
+
+    let format = image::guess_format(data).map_err(|_| MediaError::UnknownFormat)?;
+
+    let (width, height) = ImageReader::with_format(Cursor::new(data), format)
+        .into_dimensions()
+        .map_err(|error| MediaError::Decode(error.to_string()))?;
+    if width > limits.max_width || height > limits.max_height {
+        return Err(MediaError::DimensionsExceeded { width, height });
+    }
+
+    let mut decoder_limits = Limits::default();
+    decoder_limits.max_image_width = Some(limits.max_width);
+    decoder_limits.max_image_height = Some(limits.max_height);
+    decoder_limits.max_alloc = Some(limits.max_decoded_bytes);
+
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(decoder_limits);
+    reader
+        .decode()
+        .map_err(|error| MediaError::Decode(error.to_string()))?;
+
+    Ok(MediaInfo {
+        format,
+        width,
+        height,
+    })
+}
+
+/// Saves a rejected payload in `directory`, next to a description of why it has been rejected.
+/// Returns the path of the saved payload
+/// # Errors
+/// Returns an error if the files cannot be written
+pub fn quarantine(
+    directory: &Path,
+    name: &str,
+    source: NodeId,
+    data: &[u8],
+    error: &MediaError,
+) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+
+    let file_name = format!("{:016x}", content_hash(data));
+    let payload_path = directory.join(format!("{file_name}.bin"));
+    std::fs::write(&payload_path, data)?;
+
+    let description = format!("name: {name}\nsource: {source}\nsize: {}\nerror: {error}\n", data.len());
+    std::fs::write(directory.join(format!("{file_name}.txt")), description)?;
+
+    Ok(payload_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, RgbImage};
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut data, ImageFormat::Png)
+            .expect("The image can be encoded");
+        data.into_inner()
+    }
+
+    #[test]
+    fn accepts_images_within_the_limits() {
+        let info = validate_media(&png(4, 3), &MediaLimits::default()).expect("The image is valid");
+        assert_eq!(info.format, ImageFormat::Png);
+        assert_eq!((info.width, info.height), (4, 3));
+    }
+
+    #[test]
+    fn rejects_empty_and_unknown_payloads() {
+        assert_eq!(validate_media(&[], &MediaLimits::default()).unwrap_err(), MediaError::Empty);
+        assert_eq!(
+            validate_media(b"not an image", &MediaLimits::default()).unwrap_err(),
+            MediaError::UnknownFormat
+        );
+    }
+
+    #[test]
+    fn rejects_payloads_over_the_byte_limit() {
+        let data = png(4, 4);
+        let limits = MediaLimits {
+            max_bytes: data.len() - 1,
+            ..MediaLimits::default()
+        };
+        assert_eq!(
+            validate_media(&data, &limits).unwrap_err(),
+            MediaError::TooLarge {
+                size: data.len(),
+                max: data.len() - 1
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_images_over_the_dimension_limits() {
+        let limits = MediaLimits {
+            max_width: 16,
+            max_height: 16,
+            ..MediaLimits::default()
+        };
+        assert_eq!(
+            validate_media(&png(17, 2), &limits).unwrap_err(),
+            MediaError::DimensionsExceeded { width: 17, height: 2 }
+        );
+        assert_eq!(
+            validate_media(&png(2, 17), &limits).unwrap_err(),
+            MediaError::DimensionsExceeded { width: 2, height: 17 }
+        );
+    }
+
+    #[test]
+    fn rejects_images_decoding_over_the_memory_limit() {
+        // 256x256 RGB pixels take 196608 bytes once decoded, while the PNG is tiny
+        let data = png(256, 256);
+        let limits = MediaLimits {
+            max_decoded_bytes: 64 * 1024,
+            ..MediaLimits::default()
+        };
+        assert!(data.len() < 64 * 1024);
+        assert!(matches!(validate_media(&data, &limits), Err(MediaError::Decode(_))));
+        assert!(validate_media(&data, &MediaLimits::default()).is_ok());
+    }
+
+    #[test]
+    fn quarantine_saves_the_payload_and_the_reason() {
+        let directory = std::env::temp_dir().join(format!("ap_client_quarantine_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+
+        let data = b"not an image";
+        let path = quarantine(&directory, "a.png", 7, data, &MediaError::UnknownFormat)
+            .expect("The payload can be quarantined");
+        assert!(path.starts_with(&directory));
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        let description = std::fs::read_to_string(path.with_extension("txt")).unwrap();
+        assert!(description.contains("name: a.png"), "{description}");
+        assert!(description.contains("source: 7"), "{description}");
+        assert!(description.contains("unknown image format"), "{description}");
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use wg_2024::network::NodeId;
+use crate::media_sink::content_hash;
+
+/// Identifies a media: its name on the server that serves it
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MediaKey {
+    pub server: NodeId,
+    pub name: String,
+}
+
+impl MediaKey {
+    #[must_use]
+    pub fn new(server: NodeId, name: &str) -> Self {
+        Self {
+            server,
+            name: name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    data: Arc<[u8]>,
+    hash: u64,
+    last_used: u64,
+}
+
+/// What to do with a media the client wants to fetch
+#[derive(Debug)]
+pub enum MediaLookup {
+    /// The media is cached and can be served locally
+    Cached(Arc<[u8]>),
+    /// A request for the media is already in flight with the contained session
+    InFlight(u64),
+    /// The media has to be requested
+    Missing,
+}
+
+/// Cache of the received media, within a memory budget, evicting the least recently used ones.
+/// It also tracks the media being fetched, so that the same media is never requested twice at once
+#[derive(Debug)]
+pub struct MediaCache {
+    budget: usize,
+    used: usize,
+    clock: u64,
+    entries: HashMap<MediaKey, CacheEntry>,
+    in_flight: HashMap<MediaKey, u64>,
+}
+
+impl MediaCache {
+    /// Creates a cache holding at most `budget` bytes of media. A budget of 0 disables caching,
+    /// but in-flight requests are still deduplicated
+    #[must_use]
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            used: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Looks `key` up, marking it as the most recently used media if it is cached.
+    /// If `expected_hash` is set, a cached media with different content is a miss, to be fetched again
+    pub fn lookup(&mut self, key: &MediaKey, expected_hash: Option<u64>) -> MediaLookup {
+        self.clock += 1;
+        if let Some(entry) = self.entries.get_mut(key) {
+            if expected_hash.is_none_or(|hash| hash == entry.hash) {
+                entry.last_used = self.clock;
+                return MediaLookup::Cached(entry.data.clone());
+            }
+            log::warn!("Cached media '{}' of {} does not match its expected content", key.name, key.server);
+        }
+
+        match self.in_flight.get(key) {
+            Some(session_id) => MediaLookup::InFlight(*session_id),
+            None => MediaLookup::Missing,
+        }
+    }
+
+    /// Records that `key` has been requested with `session_id`
+    pub fn mark_in_flight(&mut self, key: MediaKey, session_id: u64) {
+        self.in_flight.insert(key, session_id);
+    }
+
+    /// Records that the request for `key` ended without a media
+    pub fn cancel(&mut self, key: &MediaKey) {
+        self.in_flight.remove(key);
+    }
+
+    /// Stores the received media, evicting the least recently used ones to stay within the budget.
+    /// Returns its content hash
+    pub fn insert(&mut self, key: MediaKey, data: &[u8]) -> u64 {
+        self.in_flight.remove(&key);
+        let hash = content_hash(data);
+
+        if let Some(previous) = self.entries.remove(&key) {
+            self.used -= previous.data.len();
+        }
+        if data.len() > self.budget {
+            return hash;
+        }
+
+        while self.used + data.len() > self.budget {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&oldest) {
+                log::debug!("Media '{}' of {} evicted from cache", oldest.name, oldest.server);
+                self.used -= evicted.data.len();
+            }
+        }
+
+        self.clock += 1;
+        self.used += data.len();
+        let entry = CacheEntry {
+            data: Arc::from(data),
+            hash,
+            last_used: self.clock,
+        };
+        self.entries.insert(key, entry);
+        hash
+    }
+
+    /// Returns the number of bytes currently cached
+    #[must_use]
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> MediaKey {
+        MediaKey::new(1, name)
+    }
+
+    fn is_cached(cache: &mut MediaCache, name: &str) -> bool {
+        matches!(cache.lookup(&key(name), None), MediaLookup::Cached(_))
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used_media() {
+        let mut cache = MediaCache::new(10);
+        cache.insert(key("a"), &[0; 4]);
+        cache.insert(key("b"), &[0; 4]);
+        assert!(is_cached(&mut cache, "a"));
+
+        cache.insert(key("c"), &[0; 4]);
+        assert!(is_cached(&mut cache, "a"));
+        assert!(!is_cached(&mut cache, "b"));
+        assert!(is_cached(&mut cache, "c"));
+        assert_eq!(cache.used(), 8);
+    }
+
+    #[test]
+    fn does_not_cache_media_over_the_budget() {
+        let mut cache = MediaCache::new(4);
+        cache.insert(key("a"), &[0; 2]);
+        cache.insert(key("b"), &[0; 5]);
+        assert!(is_cached(&mut cache, "a"));
+        assert!(!is_cached(&mut cache, "b"));
+        assert_eq!(cache.used(), 2);
+    }
+
+    #[test]
+    fn replacing_a_media_updates_the_used_bytes() {
+        let mut cache = MediaCache::new(10);
+        cache.insert(key("a"), &[0; 4]);
+        cache.insert(key("a"), &[0; 6]);
+        assert_eq!(cache.used(), 6);
+    }
+
+    #[test]
+    fn coalesces_requests_for_a_media_in_flight() {
+        let mut cache = MediaCache::new(0);
+        assert!(matches!(cache.lookup(&key("a"), None), MediaLookup::Missing));
+
+        cache.mark_in_flight(key("a"), 7);
+        assert!(matches!(cache.lookup(&key("a"), None), MediaLookup::InFlight(7)));
+        assert!(matches!(cache.lookup(&MediaKey::new(2, "a"), None), MediaLookup::Missing));
+
+        cache.insert(key("a"), &[0; 4]);
+        assert!(matches!(cache.lookup(&key("a"), None), MediaLookup::Missing));
+    }
+
+    #[test]
+    fn cancelled_requests_are_no_longer_in_flight() {
+        let mut cache = MediaCache::new(10);
+        cache.mark_in_flight(key("a"), 7);
+        cache.cancel(&key("a"));
+        assert!(matches!(cache.lookup(&key("a"), None), MediaLookup::Missing));
+    }
+
+    #[test]
+    fn mismatched_hash_forces_a_refetch() {
+        let mut cache = MediaCache::new(10);
+        let hash = cache.insert(key("a"), &[1, 2, 3]);
+        assert_eq!(hash, content_hash(&[1, 2, 3]));
+        assert!(matches!(cache.lookup(&key("a"), Some(hash)), MediaLookup::Cached(_)));
+
+        let changed = content_hash(&[3, 2, 1]);
+        assert!(matches!(cache.lookup(&key("a"), Some(changed)), MediaLookup::Missing));
+
+        cache.insert(key("a"), &[3, 2, 1]);
+        match cache.lookup(&key("a"), Some(changed)) {
+            MediaLookup::Cached(data) => assert_eq!(&*data, [3, 2, 1]),
+            other => panic!("expected the refetched media, got {other:?}"),
+        }
+    }
+}
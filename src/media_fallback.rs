@@ -0,0 +1,112 @@
+use std::collections::{HashMap, VecDeque};
+use wg_2024::network::NodeId;
+use crate::media_cache::MediaKey;
+
+/// Which other servers are asked for a media that a server does not have
+#[derive(Debug, Clone)]
+pub struct MediaFallbackPolicy {
+    /// Media servers asked first, in order. The media servers of the `ServerDirectory` are asked next
+    pub servers: Vec<NodeId>,
+    /// Maximum number of other servers asked for the same media. 0 disables the fallback
+    pub max_servers: usize,
+}
+
+impl Default for MediaFallbackPolicy {
+    fn default() -> Self {
+        Self {
+            servers: Vec::new(),
+            max_servers: 3,
+        }
+    }
+}
+
+/// A media being requested from other servers than the one it was first requested from
+#[derive(Debug, Clone)]
+pub struct Fallback {
+    /// The first request of the media
+    pub origin: MediaKey,
+    /// The other servers asked so far, in order
+    pub tried: Vec<NodeId>,
+}
+
+/// Tracks the fallback requests, keyed by the server and name of their current attempt.
+/// Fallbacks of different origins can ask the same server for the same name at once: each of them
+/// sent its own request, so they wait in order for a response each
+#[derive(Debug, Default)]
+pub struct MediaFallbacks {
+    attempts: HashMap<MediaKey, VecDeque<Fallback>>,
+}
+
+impl MediaFallbacks {
+    /// Removes and returns the oldest fallback whose current attempt is `attempt`.
+    /// If there is none, `attempt` was the first request of the media
+    pub fn take(&mut self, attempt: &MediaKey) -> Fallback {
+        let fallback = self.attempts.get_mut(attempt).and_then(VecDeque::pop_front);
+        if self.attempts.get(attempt).is_some_and(VecDeque::is_empty) {
+            self.attempts.remove(attempt);
+        }
+        fallback.unwrap_or_else(|| Fallback {
+            origin: attempt.clone(),
+            tried: Vec::new(),
+        })
+    }
+
+    /// Returns the next server to ask for the media of `fallback`, among `candidates`
+    pub fn next_server(
+        fallback: &Fallback,
+        candidates: impl IntoIterator<Item = NodeId>,
+        policy: &MediaFallbackPolicy,
+    ) -> Option<NodeId> {
+        if fallback.tried.len() >= policy.max_servers {
+            return None;
+        }
+        candidates.into_iter().find(|server| {
+            *server != fallback.origin.server && !fallback.tried.contains(server)
+        })
+    }
+
+    /// Records that the media of `fallback` is now requested from `server`
+    pub fn attempt(&mut self, server: NodeId, mut fallback: Fallback) {
+        fallback.tried.push(server);
+        let attempt = MediaKey::new(server, &fallback.origin.name);
+        self.attempts.entry(attempt).or_default().push_back(fallback);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_fallbacks_to_the_same_server_are_all_resolved() {
+        let mut fallbacks = MediaFallbacks::default();
+        let first = fallbacks.take(&MediaKey::new(1, "a.png"));
+        let second = fallbacks.take(&MediaKey::new(2, "a.png"));
+        fallbacks.attempt(3, first);
+        fallbacks.attempt(3, second);
+
+        let attempt = MediaKey::new(3, "a.png");
+        assert_eq!(fallbacks.take(&attempt).origin, MediaKey::new(1, "a.png"));
+        assert_eq!(fallbacks.take(&attempt).origin, MediaKey::new(2, "a.png"));
+        let direct = fallbacks.take(&attempt);
+        assert_eq!(direct.origin, attempt);
+        assert!(direct.tried.is_empty());
+    }
+
+    #[test]
+    fn next_server_skips_the_tried_ones_up_to_the_limit() {
+        let policy = MediaFallbackPolicy {
+            servers: Vec::new(),
+            max_servers: 2,
+        };
+        let mut fallback = Fallback {
+            origin: MediaKey::new(1, "a.png"),
+            tried: Vec::new(),
+        };
+        assert_eq!(MediaFallbacks::next_server(&fallback, [1, 2, 3], &policy), Some(2));
+        fallback.tried.push(2);
+        assert_eq!(MediaFallbacks::next_server(&fallback, [1, 2, 3], &policy), Some(3));
+        fallback.tried.push(3);
+        assert_eq!(MediaFallbacks::next_server(&fallback, [1, 2, 3, 4], &policy), None);
+    }
+}
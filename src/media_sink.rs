@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Destination of the media received by the client
+pub trait MediaSink: Send {
+    /// Handles the media called `name`, whose content is `data`.
+    /// Returns the path where the media has been stored, if it has been stored on disk
+    /// # Errors
+    /// Returns an error if the media cannot be stored
+    fn store(&mut self, name: &str, data: &[u8]) -> std::io::Result<Option<PathBuf>>;
+}
+
+/// Returns the 64-bit FNV-1a hash of `data`, stable across runs and platforms
+#[must_use]
+pub fn content_hash(data: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    data.iter().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(PRIME)
+    })
+}
+
+/// Returns the file name of a media: its content hash and the extension of `name`,
+/// or the one of the detected image format
+fn content_file_name(name: &str, data: &[u8]) -> String {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+        .or_else(|| {
+            image::guess_format(data)
+                .ok()
+                .and_then(|format| format.extensions_str().first())
+                .map(ToString::to_string)
+        })
+        .unwrap_or_else(|| "bin".to_string());
+
+    format!("{:016x}.{extension}", content_hash(data))
+}
+
+/// Writes `data` in `directory` using its content hash as file name, unless it is already there
+pub(crate) fn write_content_addressed(directory: &Path, name: &str, data: &[u8]) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+    let path = directory.join(content_file_name(name, data));
+    if !path.exists() {
+        std::fs::write(&path, data)?;
+    }
+    Ok(path)
+}
+
+/// Saves the media in a directory, naming the files after their content hash
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    directory: PathBuf,
+}
+
+impl DirectorySink {
+    #[must_use]
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+impl MediaSink for DirectorySink {
+    fn store(&mut self, name: &str, data: &[u8]) -> std::io::Result<Option<PathBuf>> {
+        let path = write_content_addressed(&self.directory, name, data)?;
+        log::info!("Media '{name}' saved to {}", path.display());
+        Ok(Some(path))
+    }
+}
+
+/// Keeps the media in memory, indexed by name. The stored media can be read through `MemorySink::media`
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    media: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+}
+
+impl MemorySink {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the shared map of the stored media, which stays valid after the sink is moved into a client
+    #[must_use]
+    pub fn media(&self) -> Arc<Mutex<HashMap<String, Vec<u8>>>> {
+        self.media.clone()
+    }
+}
+
+impl MediaSink for MemorySink {
+    fn store(&mut self, name: &str, data: &[u8]) -> std::io::Result<Option<PathBuf>> {
+        let mut media = self
+            .media
+            .lock()
+            .map_err(|error| std::io::Error::other(error.to_string()))?;
+        media.insert(name.to_string(), data.to_vec());
+        Ok(None)
+    }
+}
+
+/// Drops every media
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DiscardSink;
+
+impl MediaSink for DiscardSink {
+    fn store(&mut self, name: &str, data: &[u8]) -> std::io::Result<Option<PathBuf>> {
+        log::debug!("Media '{name}' of {} bytes discarded", data.len());
+        Ok(None)
+    }
+}
+
+/// Saves the media in the temporary directory and opens them with the default system viewer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ViewerSink;
+
+impl MediaSink for ViewerSink {
+    fn store(&mut self, name: &str, data: &[u8]) -> std::io::Result<Option<PathBuf>> {
+        use std::process::Command;
+
+        let path = write_content_addressed(&std::env::temp_dir(), name, data)?;
+
+        // Open the image using the default system viewer
+        #[cfg(target_os = "windows")]
+        Command::new("cmd").arg("/C").arg(&path).spawn()?;
+
+        #[cfg(target_os = "macos")]
+        Command::new("open").arg(&path).spawn()?;
+
+        #[cfg(target_os = "linux")]
+        Command::new("xdg-open").arg(&path).spawn()?;
+
+        Ok(Some(path))
+    }
+}
@@ -0,0 +1,77 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, PanicHookInfo};
+use std::sync::Once;
+use wg_2024::network::NodeId;
+use crate::event::ClientEvent;
+use crate::runtime::{Component, EventSender};
+
+/// The node and the component a thread runs, used to report its panics
+pub(crate) struct PanicContext {
+    pub node_id: NodeId,
+    pub component: Component,
+    /// Shared with the runtime, so that the panic is reported to its current event channel
+    pub event_tx: EventSender,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<Option<PanicContext>> = const { RefCell::new(None) };
+}
+
+static INSTALL: Once = Once::new();
+
+/// Installs, once per process, the panic hook reporting the panics of the threads run by a `NodeRuntime`,
+/// whichever node they belong to. The hook installed before the first node started is called after it
+/// for every panic. A hook set by the application afterwards replaces it, so it has to be set before
+/// starting the nodes
+pub(crate) fn install_hook() {
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            CONTEXT.with(|context| {
+                if let Ok(context) = context.try_borrow() {
+                    if let Some(context) = context.as_ref() {
+                        report(context, info);
+                    }
+                }
+            });
+            previous(info);
+        }));
+    });
+}
+
+/// Tags the panics of the current thread with `context`
+pub(crate) fn set_context(context: PanicContext) {
+    CONTEXT.with(|current| *current.borrow_mut() = Some(context));
+}
+
+fn report(context: &PanicContext, info: &PanicHookInfo<'_>) {
+    let message = payload_message(info.payload());
+    let location = info.location().map(ToString::to_string);
+    log::error!(
+        "Node {}: the {} thread panicked at {}: {message}",
+        context.node_id,
+        context.component,
+        location.as_deref().unwrap_or("unknown location")
+    );
+
+    let event = ClientEvent::Panicked {
+        node_id: context.node_id,
+        component: context.component,
+        message,
+        location,
+    };
+    if context.event_tx.send(event).is_err() {
+        log::warn!("Cannot report the panic of node {}", context.node_id);
+    }
+}
+
+fn payload_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "non-string panic payload".to_string()
+    }
+}
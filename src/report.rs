@@ -0,0 +1,110 @@
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+use crate::response::{Captured, ResponseKind};
+
+/// What a scenario step expects to receive. Unset fields are not checked
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    pub kind: Option<ResponseKind>,
+    /// Whether the response has to be a `TextNotFound` or `MediaNotFound`
+    pub not_found: Option<bool>,
+    /// Exact number of entries of a list response, or length of the content
+    pub len: Option<usize>,
+    pub min_len: Option<usize>,
+    pub max_len: Option<usize>,
+    /// Maximum time between the request and its response
+    pub within_ms: Option<u64>,
+}
+
+impl Expectation {
+    /// Checks `captured`, received `latency` after the request.
+    /// Returns the reasons of the failure, if any
+    pub(crate) fn check(&self, captured: &Captured, latency: Duration) -> Result<(), String> {
+        let kind = captured.kind();
+        let len = captured.len();
+        let mut failures = Vec::new();
+
+        if let Some(expected) = self.kind {
+            if expected != kind {
+                failures.push(format!("expected {expected:?}, received {kind:?}"));
+            }
+        }
+        if let Some(not_found) = self.not_found {
+            let is_not_found = matches!(kind, ResponseKind::TextNotFound | ResponseKind::MediaNotFound);
+            if not_found != is_not_found {
+                failures.push(format!("expected not_found = {not_found}, received {kind:?}"));
+            }
+        }
+        if let Some(expected) = self.len {
+            if expected != len {
+                failures.push(format!("expected length {expected}, received {len}"));
+            }
+        }
+        if let Some(min) = self.min_len {
+            if len < min {
+                failures.push(format!("expected length at least {min}, received {len}"));
+            }
+        }
+        if let Some(max) = self.max_len {
+            if len > max {
+                failures.push(format!("expected length at most {max}, received {len}"));
+            }
+        }
+        if let Some(within_ms) = self.within_ms {
+            if latency > Duration::from_millis(within_ms) {
+                failures.push(format!("expected response within {within_ms} ms, received after {latency:?}"));
+            }
+        }
+
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures.join("; "))
+        }
+    }
+}
+
+/// Outcome of a single `Expectation`
+#[derive(Debug, Clone, Serialize)]
+pub struct ExpectationResult {
+    /// Index of the step of the scenario file declaring the expectation. The steps are counted
+    /// from 0 in order of appearance, the ones nested in loops and conditions included
+    pub step: usize,
+    /// Session of the first attempt of the request, for request steps
+    pub session_id: Option<u64>,
+    pub destination: Option<NodeId>,
+    pub passed: bool,
+    /// Kind of the received response, or the reason of the failure
+    pub detail: String,
+    /// Time between the request, or the start of the wait, and the response
+    pub latency_ms: Option<u64>,
+    /// Time between the start of the scenario and the check
+    pub at_ms: u64,
+}
+
+/// Summary of the expectations checked while running a scenario
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ScenarioReport {
+    /// `false` if the client stopped before the end of the scenario, or if the scenario was aborted
+    pub completed: bool,
+    /// Why the scenario was aborted before its end, if it was
+    pub error: Option<String>,
+    pub passed: usize,
+    pub failed: usize,
+    pub duration_ms: u64,
+    pub results: Vec<ExpectationResult>,
+}
+
+impl ScenarioReport {
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.completed && self.failed == 0
+    }
+}
+
+/// Converts `duration` to milliseconds, saturating at `u64::MAX`
+pub(crate) fn as_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
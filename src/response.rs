@@ -0,0 +1,129 @@
+use messages::{ChatResponse, MediaResponse, ResponseType, TextResponse};
+use serde::{Deserialize, Serialize};
+use wg_2024::network::NodeId;
+
+/// The variant of a `ResponseType`, flattened over all the response families
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ResponseKind {
+    TextList,
+    Text,
+    TextNotFound,
+    MediaList,
+    Media,
+    MediaNotFound,
+    ClientList,
+    MessageFrom,
+    MessageSent,
+    Discovery,
+}
+
+impl ResponseKind {
+    #[must_use]
+    pub fn of(response: &ResponseType) -> Self {
+        match response {
+            ResponseType::TextResponse(TextResponse::TextList(_)) => ResponseKind::TextList,
+            ResponseType::TextResponse(TextResponse::Text(_)) => ResponseKind::Text,
+            ResponseType::TextResponse(TextResponse::NotFound(_)) => ResponseKind::TextNotFound,
+            ResponseType::MediaResponse(MediaResponse::MediaList(_)) => ResponseKind::MediaList,
+            ResponseType::MediaResponse(MediaResponse::Media(_)) => ResponseKind::Media,
+            ResponseType::MediaResponse(MediaResponse::NotFound(_)) => ResponseKind::MediaNotFound,
+            ResponseType::ChatResponse(ChatResponse::ClientList(_)) => ResponseKind::ClientList,
+            ResponseType::ChatResponse(ChatResponse::MessageFrom { .. }) => ResponseKind::MessageFrom,
+            ResponseType::ChatResponse(ChatResponse::MessageSent) => ResponseKind::MessageSent,
+            ResponseType::DiscoveryResponse(_) => ResponseKind::Discovery,
+        }
+    }
+}
+
+/// A response received from `source`, as seen by scenarios
+#[derive(Debug, Clone)]
+pub struct Captured {
+    pub source: NodeId,
+    pub response: ResponseType,
+}
+
+impl Captured {
+    #[must_use]
+    pub fn kind(&self) -> ResponseKind {
+        ResponseKind::of(&self.response)
+    }
+
+    /// Returns the entries of a list response, `None` for the other responses
+    #[must_use]
+    pub fn items(&self) -> Option<Vec<String>> {
+        match &self.response {
+            ResponseType::TextResponse(TextResponse::TextList(list))
+            | ResponseType::MediaResponse(MediaResponse::MediaList(list)) => Some(list.clone()),
+            ResponseType::ChatResponse(ChatResponse::ClientList(list)) => {
+                Some(list.iter().map(ToString::to_string).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the main textual content of the response
+    #[must_use]
+    pub fn text(&self) -> String {
+        match &self.response {
+            ResponseType::TextResponse(TextResponse::Text(text)) => text.clone(),
+            ResponseType::TextResponse(TextResponse::NotFound(name))
+            | ResponseType::MediaResponse(MediaResponse::NotFound(name)) => name.clone(),
+            ResponseType::ChatResponse(ChatResponse::MessageFrom { message, .. }) => {
+                message.clone()
+            }
+            ResponseType::MediaResponse(MediaResponse::Media(data)) => {
+                format!("<{} bytes>", data.len())
+            }
+            ResponseType::ChatResponse(ChatResponse::MessageSent) => String::new(),
+            ResponseType::DiscoveryResponse(server_type) => format!("{server_type:?}"),
+            _ => self.items().unwrap_or_default().join(","),
+        }
+    }
+
+    /// Returns the number of entries of a list response, or the length of its content
+    #[must_use]
+    pub fn len(&self) -> usize {
+        match &self.response {
+            ResponseType::MediaResponse(MediaResponse::Media(data)) => data.len(),
+            _ => self
+                .items()
+                .map_or_else(|| self.text().len(), |items| items.len()),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the value selected by `accessor`, which is one of:
+    /// - empty: the main content of the response
+    /// - `[i]`: the `i`-th entry of a list
+    /// - `.len`: the number of entries or the length of the content
+    /// - `.source`: the node that sent the response
+    /// - `.from`: the sender of a `MessageFrom`
+    /// - `.kind`: the `ResponseKind`
+    #[must_use]
+    pub fn get(&self, accessor: &str) -> Option<String> {
+        match accessor {
+            "" => Some(self.text()),
+            ".len" => Some(self.len().to_string()),
+            ".source" => Some(self.source.to_string()),
+            ".kind" => Some(format!("{:?}", self.kind())),
+            ".from" => match &self.response {
+                ResponseType::ChatResponse(ChatResponse::MessageFrom { from, .. }) => {
+                    Some(from.to_string())
+                }
+                _ => None,
+            },
+            _ => {
+                let index = accessor
+                    .strip_prefix('[')?
+                    .strip_suffix(']')?
+                    .parse::<usize>()
+                    .ok()?;
+                self.items()?.get(index).cloned()
+            }
+        }
+    }
+}
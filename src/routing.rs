@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::mem::{discriminant, Discriminant};
+use std::time::Duration;
+use messages::ServerType;
+use rand::Rng;
+use wg_2024::network::NodeId;
+use crate::directory::ServerDirectory;
+
+/// How a destination is chosen among the known servers of the requested type
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RoutingStrategy {
+    Random,
+    /// Every request goes to the next server, in ascending `NodeId` order
+    RoundRobin,
+    /// The server that answered the last `DiscoveryRequest` the fastest
+    #[default]
+    LowestLatency,
+}
+
+/// Picks the servers of the scenario steps addressed to a server type
+#[derive(Debug)]
+pub struct Router {
+    directory: ServerDirectory,
+    strategy: RoutingStrategy,
+    next_index: HashMap<Discriminant<ServerType>, usize>,
+    discovering: bool,
+}
+
+impl Router {
+    /// Creates a router choosing among the servers of `directory`.
+    /// `discovering` tells whether new servers can still be discovered
+    pub fn new(directory: ServerDirectory, strategy: RoutingStrategy, discovering: bool) -> Self {
+        Self {
+            directory,
+            strategy,
+            next_index: HashMap::new(),
+            discovering,
+        }
+    }
+
+    /// Returns `true` if a step can wait for a server of a type that is not known yet
+    pub fn can_wait(&self) -> bool {
+        self.discovering
+    }
+
+    /// Sets whether new servers can still be discovered: once they cannot, the steps addressed to
+    /// an unknown server type are given up instead of waiting forever
+    pub fn set_discovering(&mut self, discovering: bool) {
+        self.discovering = discovering;
+    }
+
+    /// Returns `true` if some known server of type `server_type` satisfies `available`
+    pub fn has_candidate<F: Fn(NodeId) -> bool>(&self, server_type: &ServerType, available: F) -> bool {
+        self.directory
+            .servers_of_type(server_type)
+            .into_iter()
+            .any(available)
+    }
+
+    /// Returns `true` if no server of type `server_type` is known
+    pub fn is_unknown(&self, server_type: &ServerType) -> bool {
+        self.directory.servers_of_type(server_type).is_empty()
+    }
+
+    /// Chooses a server of type `server_type` among the known ones satisfying `available`
+    pub fn choose<F: Fn(NodeId) -> bool>(&mut self, server_type: &ServerType, available: F) -> Option<NodeId> {
+        let candidates: Vec<NodeId> = self
+            .directory
+            .servers_of_type(server_type)
+            .into_iter()
+            .filter(|server| available(*server))
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let chosen = match self.strategy {
+            RoutingStrategy::Random => candidates[rand::rng().random_range(0..candidates.len())],
+            RoutingStrategy::RoundRobin => {
+                let next_index = self.next_index.entry(discriminant(server_type)).or_default();
+                let chosen = candidates[*next_index % candidates.len()];
+                *next_index = next_index.wrapping_add(1);
+                chosen
+            }
+            RoutingStrategy::LowestLatency => candidates
+                .iter()
+                .copied()
+                .min_by_key(|server| {
+                    self.directory
+                        .get(*server)
+                        .and_then(|entry| entry.latency)
+                        .unwrap_or(Duration::MAX)
+                })
+                .unwrap_or(candidates[0]),
+        };
+        log::debug!("Routing to {chosen} among the {server_type:?} servers {candidates:?}");
+        Some(chosen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn directory(servers: &[(NodeId, ServerType, Option<u64>)]) -> ServerDirectory {
+        let directory = ServerDirectory::new();
+        for (server, server_type, latency_ms) in servers {
+            directory.record(*server, server_type, latency_ms.map(Duration::from_millis));
+        }
+        directory
+    }
+
+    fn router(strategy: RoutingStrategy, servers: &[(NodeId, ServerType, Option<u64>)]) -> Router {
+        Router::new(directory(servers), strategy, false)
+    }
+
+    #[test]
+    fn random_chooses_among_the_available_candidates() {
+        let mut single = router(RoutingStrategy::Random, &[(3, ServerType::Text, None), (4, ServerType::Media, None)]);
+        assert_eq!(single.choose(&ServerType::Text, |_| true), Some(3));
+
+        let mut router = router(
+            RoutingStrategy::Random,
+            &[(1, ServerType::Text, None), (2, ServerType::Text, None), (3, ServerType::Text, None)],
+        );
+        for _ in 0..100 {
+            let chosen = router.choose(&ServerType::Text, |server| server != 2);
+            assert!(matches!(chosen, Some(1 | 3)), "{chosen:?}");
+        }
+        assert_eq!(router.choose(&ServerType::Chat, |_| true), None);
+    }
+
+    #[test]
+    fn round_robin_rotates_in_ascending_order_per_type() {
+        let mut router = router(
+            RoutingStrategy::RoundRobin,
+            &[
+                (3, ServerType::Text, None),
+                (1, ServerType::Text, None),
+                (2, ServerType::Text, None),
+                (9, ServerType::Media, None),
+            ],
+        );
+        let texts: Vec<Option<NodeId>> = (0..4).map(|_| router.choose(&ServerType::Text, |_| true)).collect();
+        assert_eq!(texts, [Some(1), Some(2), Some(3), Some(1)]);
+        assert_eq!(router.choose(&ServerType::Media, |_| true), Some(9));
+        assert_eq!(router.choose(&ServerType::Text, |_| true), Some(2));
+    }
+
+    #[test]
+    fn lowest_latency_prefers_the_fastest_then_the_lowest_id() {
+        let mut router = router(
+            RoutingStrategy::LowestLatency,
+            &[
+                (1, ServerType::Text, None),
+                (2, ServerType::Text, Some(30)),
+                (3, ServerType::Text, Some(10)),
+                (4, ServerType::Text, Some(10)),
+            ],
+        );
+        assert_eq!(router.choose(&ServerType::Text, |_| true), Some(3));
+        assert_eq!(router.choose(&ServerType::Text, |server| server != 3), Some(4));
+        assert_eq!(router.choose(&ServerType::Text, |server| server == 1 || server == 2), Some(2));
+        assert_eq!(router.choose(&ServerType::Text, |server| server == 1), Some(1));
+    }
+
+    #[test]
+    fn waits_for_unknown_types_only_while_discovering() {
+        let mut router = router(RoutingStrategy::default(), &[(1, ServerType::Text, None)]);
+        assert!(router.is_unknown(&ServerType::Chat));
+        assert!(!router.is_unknown(&ServerType::Text));
+        assert!(!router.can_wait());
+        router.set_discovering(true);
+        assert!(router.can_wait());
+    }
+}
@@ -0,0 +1,695 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use messages::{ErrorType, RequestType, ResponseType};
+use wg_2024::network::NodeId;
+use crate::config::PipelineLimits;
+use crate::report::{as_millis, Expectation, ExpectationResult, ScenarioReport};
+use crate::response::Captured;
+use crate::routing::Router;
+use crate::scenario::{Instruction, Scenario, Target};
+use crate::session::SessionTable;
+use crate::state::ScenarioProgress;
+
+/// Maximum number of received responses kept for the `WaitFor` steps
+const UNCONSUMED_HISTORY: usize = 64;
+
+/// Maximum number of control instructions executed without sending any request,
+/// to stop scenarios looping forever on a condition that cannot change
+const MAX_CONTROL_STEPS: usize = 10_000;
+
+/// A request the scenario wants to send
+#[derive(Debug)]
+pub struct Dispatch {
+    pub instruction: usize,
+    pub destination: NodeId,
+    pub request: RequestType,
+}
+
+/// A scenario request waiting for its response
+#[derive(Debug, Clone, Copy)]
+struct InFlight {
+    instruction: usize,
+    destination: NodeId,
+    sent_at: Instant,
+}
+
+/// Executes a `Scenario`, one instruction at a time
+#[derive(Debug)]
+pub struct ScenarioRunner {
+    scenario: Scenario,
+    pc: usize,
+    counters: Vec<u32>,
+    variables: HashMap<String, Captured>,
+    next_dispatch_at: Instant,
+    /// Scenario requests waiting for a response, keyed by the session of their first attempt
+    in_flight: HashMap<u64, InFlight>,
+    /// Sessions of the requests of a replaced scenario, whose responses are not kept for the `WaitFor` steps
+    retired: HashSet<u64>,
+    waiting_since: Option<Instant>,
+    unconsumed: VecDeque<Captured>,
+    started_at: Instant,
+    results: Vec<ExpectationResult>,
+    finished: bool,
+    /// Why the scenario was stopped before its end
+    aborted: Option<String>,
+    reported: bool,
+    paused: bool,
+    /// Delay after every `Send` replacing the ones of the scenario
+    sleep_time: Option<Duration>,
+}
+
+impl ScenarioRunner {
+    pub fn new(scenario: Scenario) -> Self {
+        let counters = vec![0; scenario.counters];
+        Self {
+            scenario,
+            pc: 0,
+            counters,
+            variables: HashMap::new(),
+            next_dispatch_at: Instant::now(),
+            in_flight: HashMap::new(),
+            retired: HashSet::new(),
+            waiting_since: None,
+            unconsumed: VecDeque::new(),
+            started_at: Instant::now(),
+            results: Vec::new(),
+            finished: false,
+            aborted: None,
+            reported: false,
+            paused: false,
+            sleep_time: None,
+        }
+    }
+
+    /// Runs the program until it needs to send a request, which is returned,
+    /// or until it has to wait for a response, a delay or the pipeline limits
+    pub fn next_dispatch(
+        &mut self,
+        sessions: &SessionTable,
+        limits: &PipelineLimits,
+        router: &mut Router,
+    ) -> Option<Dispatch> {
+        self.collect_completed(sessions);
+        if self.paused {
+            return None;
+        }
+
+        let mut control_steps = 0;
+        loop {
+            let Some(instruction) = self.scenario.program.get(self.pc) else {
+                if !self.finished && self.in_flight.is_empty() {
+                    self.finished = true;
+                    if self.aborted.is_none() {
+                        log::info!("Scenario completed");
+                    }
+                }
+                return None;
+            };
+
+            control_steps += 1;
+            if control_steps > MAX_CONTROL_STEPS {
+                let error = format!(
+                    "aborted at step {}: too many steps without sending any request",
+                    self.scenario.step_of(self.pc)
+                );
+                log::error!("Scenario {error}");
+                self.aborted = Some(error);
+                self.pc = self.scenario.program.len();
+                continue;
+            }
+
+            match instruction {
+                Instruction::Send {
+                    destination,
+                    request,
+                    save_as,
+                    expect,
+                    ..
+                } => {
+                    if Instant::now() < self.next_dispatch_at {
+                        return None;
+                    }
+
+                    let destination = match destination {
+                        Target::Node(node_id) => {
+                            if !self.can_dispatch(*node_id, limits) {
+                                return None;
+                            }
+                            *node_id
+                        }
+                        Target::AnyOf(server_type) => {
+                            let chosen = router
+                                .choose(server_type, |server| self.can_dispatch(server, limits));
+                            match chosen {
+                                Some(server) => server,
+                                None if router.can_wait() || !router.is_unknown(server_type) => {
+                                    return None;
+                                }
+                                None => {
+                                    log::error!(
+                                        "Scenario step {}: no {server_type:?} server known, step skipped",
+                                        self.scenario.step_of(self.pc)
+                                    );
+                                    if expect.is_some() {
+                                        let result = self.failure(
+                                            self.pc,
+                                            None,
+                                            None,
+                                            format!("no {server_type:?} server known"),
+                                        );
+                                        self.results.push(result);
+                                    }
+                                    self.pc += 1;
+                                    continue;
+                                }
+                            }
+                        }
+                    };
+
+                    let instruction = self.pc;
+                    self.pc += 1;
+                    match request.resolve(&self.variables) {
+                        Ok(request) => {
+                            if let Some(name) = save_as {
+                                self.variables.remove(name);
+                            }
+                            return Some(Dispatch {
+                                instruction,
+                                destination,
+                                request,
+                            });
+                        }
+                        Err(error) => {
+                            log::error!("Scenario step {} skipped: {error}", self.scenario.step_of(instruction));
+                            if expect.is_some() {
+                                let result = self.failure(
+                                    instruction,
+                                    None,
+                                    Some(destination),
+                                    format!("step skipped: {error}"),
+                                );
+                                self.results.push(result);
+                            }
+                        }
+                    }
+                }
+                Instruction::WaitFor {
+                    pattern,
+                    timeout,
+                    save_as,
+                    expect,
+                } => {
+                    let now = Instant::now();
+                    let waiting_since = *self.waiting_since.get_or_insert(now);
+
+                    if let Some(position) = self
+                        .unconsumed
+                        .iter()
+                        .position(|captured| pattern.matches(captured))
+                    {
+                        if let Some(captured) = self.unconsumed.remove(position) {
+                            if let Some(expect) = expect {
+                                let result = self.check(
+                                    self.pc,
+                                    None,
+                                    expect,
+                                    &captured,
+                                    now - waiting_since,
+                                );
+                                self.results.push(result);
+                            }
+                            if let Some(name) = save_as {
+                                self.variables.insert(name.clone(), captured);
+                            }
+                        }
+                        self.waiting_since = None;
+                        self.pc += 1;
+                    } else if timeout.is_some_and(|timeout| now >= waiting_since + timeout) {
+                        log::warn!(
+                            "Scenario step {}: nothing matching {pattern:?} received",
+                            self.scenario.step_of(self.pc)
+                        );
+                        if expect.is_some() {
+                            let result = self.failure(
+                                self.pc,
+                                None,
+                                pattern.from,
+                                "nothing received before the timeout".to_string(),
+                            );
+                            self.results.push(result);
+                        }
+                        self.waiting_since = None;
+                        self.pc += 1;
+                    } else {
+                        return None;
+                    }
+                }
+                Instruction::JumpUnless { condition, target } => {
+                    if !self.in_flight.is_empty() {
+                        return None;
+                    }
+                    if condition.evaluate(&self.variables) {
+                        self.pc += 1;
+                    } else {
+                        self.pc = *target;
+                    }
+                }
+                Instruction::Jump { target } => {
+                    self.pc = *target;
+                }
+                Instruction::SetCounter { counter, value } => {
+                    self.counters[*counter] = *value;
+                    self.pc += 1;
+                }
+                Instruction::CountDown { counter, target } => {
+                    if self.counters[*counter] == 0 {
+                        self.pc = *target;
+                    } else {
+                        self.counters[*counter] -= 1;
+                        self.pc += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Records that the request of `instruction` has been sent to `destination` with `session_id`
+    pub fn dispatched(
+        &mut self,
+        instruction: usize,
+        destination: NodeId,
+        session_id: u64,
+        awaiting_response: bool,
+    ) {
+        let now = Instant::now();
+        if awaiting_response {
+            let in_flight = InFlight {
+                instruction,
+                destination,
+                sent_at: now,
+            };
+            self.in_flight.insert(session_id, in_flight);
+        } else if let Some(Instruction::Send {
+            expect: Some(_), ..
+        }) = self.scenario.program.get(instruction)
+        {
+            let result = self.failure(
+                instruction,
+                Some(session_id),
+                Some(destination),
+                "the request has no response to check".to_string(),
+            );
+            self.results.push(result);
+        }
+        self.next_dispatch_at = now + self.delay_of(instruction);
+    }
+
+    /// Handles a response received from `source`. `origin_session` is the session of the
+    /// first attempt of the matching request, if any
+    pub fn on_response(
+        &mut self,
+        origin_session: Option<u64>,
+        source: NodeId,
+        response: &ResponseType,
+    ) {
+        if origin_session.is_some_and(|session_id| self.retired.remove(&session_id)) {
+            return;
+        }
+        let captured = Captured {
+            source,
+            response: response.clone(),
+        };
+
+        // A response completing a scenario request is not kept for the `WaitFor` steps
+        if let Some(session_id) = origin_session {
+            if let Some(in_flight) = self.complete(session_id) {
+                if let Some(Instruction::Send {
+                    save_as, expect, ..
+                }) = self.scenario.program.get(in_flight.instruction)
+                {
+                    if let Some(expect) = expect {
+                        let result = self.check(
+                            in_flight.instruction,
+                            Some(session_id),
+                            expect,
+                            &captured,
+                            in_flight.sent_at.elapsed(),
+                        );
+                        self.results.push(result);
+                    }
+                    if let Some(name) = save_as {
+                        self.variables.insert(name.clone(), captured);
+                    }
+                }
+                return;
+            }
+        }
+
+        self.unconsumed.push_back(captured);
+        while self.unconsumed.len() > UNCONSUMED_HISTORY {
+            self.unconsumed.pop_front();
+        }
+    }
+
+    /// Handles an error received from `source` in place of a response
+    pub fn on_error(&mut self, origin_session: u64, source: NodeId, error: &ErrorType) {
+        self.retired.remove(&origin_session);
+        if let Some(in_flight) = self.complete(origin_session) {
+            self.record_missing_response(
+                origin_session,
+                in_flight,
+                &format!("received error {error:?} from {source}"),
+            );
+        }
+    }
+
+    /// Returns the report of the expectations once the scenario has completed,
+    /// or unconditionally if `force` is set. The report is returned only once
+    pub fn take_report(&mut self, force: bool) -> Option<ScenarioReport> {
+        if self.reported || !(self.finished || force) {
+            return None;
+        }
+        self.reported = true;
+
+        let passed = self.results.iter().filter(|result| result.passed).count();
+        Some(ScenarioReport {
+            completed: self.finished && self.aborted.is_none(),
+            error: self.aborted.take(),
+            passed,
+            failed: self.results.len() - passed,
+            duration_ms: as_millis(self.started_at.elapsed()),
+            results: std::mem::take(&mut self.results),
+        })
+    }
+
+    /// Returns when the scenario can make progress without receiving anything
+    pub fn next_wakeup(&self, limits: &PipelineLimits, router: &Router) -> Option<Instant> {
+        if self.paused {
+            return None;
+        }
+        match self.scenario.program.get(self.pc)? {
+            Instruction::Send {
+                destination: Target::Node(node_id),
+                ..
+            } if self.can_dispatch(*node_id, limits) => Some(self.next_dispatch_at),
+            Instruction::Send {
+                destination: Target::AnyOf(server_type),
+                ..
+            } if router.has_candidate(server_type, |server| self.can_dispatch(server, limits))
+                || (!router.can_wait() && router.is_unknown(server_type)) =>
+            {
+                Some(self.next_dispatch_at)
+            }
+            Instruction::WaitFor {
+                timeout: Some(timeout),
+                ..
+            } => self.waiting_since.map(|since| since + *timeout),
+            _ => None,
+        }
+    }
+
+    /// Stops dispatching requests until `resume`. The requests in flight are still followed
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Waits `sleep_time` after every `Send` from now on, instead of the delays of the scenario
+    pub fn set_sleep_time(&mut self, sleep_time: Duration) {
+        self.sleep_time = Some(sleep_time);
+        self.next_dispatch_at = self.next_dispatch_at.min(Instant::now() + sleep_time);
+    }
+
+    pub fn sleep_time(&self) -> Option<Duration> {
+        self.sleep_time
+    }
+
+    /// Returns the sessions of the requests of this scenario, and of the ones it replaced, still waiting for their response
+    pub fn unfinished_sessions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.in_flight.keys().chain(&self.retired).copied()
+    }
+
+    /// Ignores the responses to `sessions`, sent by a replaced scenario
+    pub fn retire_sessions<I: IntoIterator<Item = u64>>(&mut self, sessions: I) {
+        self.retired.extend(sessions);
+    }
+
+    pub fn progress(&self) -> ScenarioProgress {
+        ScenarioProgress {
+            step: self.pc,
+            steps: self.scenario.program.len(),
+            in_flight: self.in_flight.len(),
+            finished: self.finished,
+            paused: self.paused,
+            sleep_time: self.sleep_time,
+        }
+    }
+
+    /// Removes the requests that are no longer pending in `sessions`, which have been given up
+    fn collect_completed(&mut self, sessions: &SessionTable) {
+        self.retired.retain(|session_id| sessions.is_pending(*session_id));
+        let completed: Vec<u64> = self
+            .in_flight
+            .keys()
+            .filter(|session_id| !sessions.is_pending(**session_id))
+            .copied()
+            .collect();
+        for session_id in completed {
+            if let Some(in_flight) = self.complete(session_id) {
+                self.record_missing_response(session_id, in_flight, "no response received");
+            }
+        }
+    }
+
+    /// Removes a request from the ones in flight, delaying the next dispatch by its delay
+    fn complete(&mut self, session_id: u64) -> Option<InFlight> {
+        let in_flight = self.in_flight.remove(&session_id)?;
+        let not_before = Instant::now() + self.delay_of(in_flight.instruction);
+        self.next_dispatch_at = self.next_dispatch_at.max(not_before);
+        Some(in_flight)
+    }
+
+    /// Records a failed expectation, if `instruction` declares one, for a request that got no response
+    fn record_missing_response(&mut self, session_id: u64, in_flight: InFlight, reason: &str) {
+        if let Some(Instruction::Send {
+            expect: Some(_), ..
+        }) = self.scenario.program.get(in_flight.instruction)
+        {
+            let result = self.failure(
+                in_flight.instruction,
+                Some(session_id),
+                Some(in_flight.destination),
+                reason.to_string(),
+            );
+            self.results.push(result);
+        }
+    }
+
+    fn check(
+        &self,
+        instruction: usize,
+        session_id: Option<u64>,
+        expect: &Expectation,
+        captured: &Captured,
+        latency: Duration,
+    ) -> ExpectationResult {
+        let step = self.scenario.step_of(instruction);
+        let outcome = expect.check(captured, latency);
+        if let Err(reason) = &outcome {
+            log::warn!("Scenario step {step}: expectation failed: {reason}");
+        }
+
+        ExpectationResult {
+            step,
+            session_id,
+            destination: Some(captured.source),
+            passed: outcome.is_ok(),
+            detail: outcome.map_or_else(|reason| reason, |()| format!("{:?}", captured.kind())),
+            latency_ms: Some(as_millis(latency)),
+            at_ms: as_millis(self.started_at.elapsed()),
+        }
+    }
+
+    fn failure(
+        &self,
+        instruction: usize,
+        session_id: Option<u64>,
+        destination: Option<NodeId>,
+        reason: String,
+    ) -> ExpectationResult {
+        let step = self.scenario.step_of(instruction);
+        log::warn!("Scenario step {step}: expectation failed: {reason}");
+        ExpectationResult {
+            step,
+            session_id,
+            destination,
+            passed: false,
+            detail: reason,
+            latency_ms: None,
+            at_ms: as_millis(self.started_at.elapsed()),
+        }
+    }
+
+    fn delay_of(&self, instruction: usize) -> Duration {
+        match self.scenario.program.get(instruction) {
+            Some(Instruction::Send { delay, .. }) => self.sleep_time.unwrap_or(*delay),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Returns `true` if a new request to `destination` fits the pipeline limits
+    fn can_dispatch(&self, destination: NodeId, limits: &PipelineLimits) -> bool {
+        let towards_destination = self
+            .in_flight
+            .values()
+            .filter(|in_flight| in_flight.destination == destination)
+            .count();
+
+        self.in_flight.len() < limits.max_in_flight.max(1)
+            && towards_destination < limits.max_in_flight_per_destination.max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use messages::TextResponse;
+    use super::*;
+    use crate::directory::ServerDirectory;
+    use crate::routing::RoutingStrategy;
+
+    fn runner(steps: &str) -> ScenarioRunner {
+        let scenario = Scenario::from_json(&format!(r#"{{ "steps": [{steps}] }}"#))
+            .expect("The scenario is valid");
+        ScenarioRunner::new(scenario)
+    }
+
+    fn router() -> Router {
+        Router::new(ServerDirectory::new(), RoutingStrategy::default(), false)
+    }
+
+    /// Dispatches the requests of `runner` until it stops, as if none expected a response.
+    /// Returns their destinations
+    fn dispatch_all(runner: &mut ScenarioRunner) -> Vec<NodeId> {
+        let sessions = SessionTable::default();
+        let mut router = router();
+        let mut destinations = Vec::new();
+        while let Some(dispatch) = runner.next_dispatch(&sessions, &PipelineLimits::default(), &mut router) {
+            destinations.push(dispatch.destination);
+            let session_id = destinations.len() as u64;
+            runner.dispatched(dispatch.instruction, dispatch.destination, session_id, false);
+        }
+        destinations
+    }
+
+    fn text_list() -> ResponseType {
+        ResponseType::TextResponse(TextResponse::TextList(vec!["a".to_string()]))
+    }
+
+    #[test]
+    fn repeats_counted_loops() {
+        let mut runner = runner(
+            r#"{ "loop": { "times": 3, "steps": [
+                { "to": 1, "request": { "TextRequest": "TextList" } },
+                { "to": 2, "request": { "TextRequest": "TextList" } }
+            ] } }"#,
+        );
+        assert_eq!(dispatch_all(&mut runner), [1, 2, 1, 2, 1, 2]);
+        assert!(runner.progress().finished);
+    }
+
+    #[test]
+    fn takes_the_else_branch_of_a_false_condition() {
+        let mut runner = runner(
+            r#"{ "if": { "non_empty": "list" },
+                 "then": [{ "to": 1, "request": { "TextRequest": "TextList" } }],
+                 "else": [{ "to": 2, "request": { "TextRequest": "TextList" } }] },
+               { "to": 3, "request": { "TextRequest": "TextList" } }"#,
+        );
+        assert_eq!(dispatch_all(&mut runner), [2, 3]);
+    }
+
+    #[test]
+    fn stops_scenarios_looping_without_sending() {
+        let mut runner = runner(
+            r#"{ "loop": { "steps": [
+                { "if": { "non_empty": "never" }, "then": [{ "to": 1, "request": { "TextRequest": "TextList" } }] }
+            ] } }"#,
+        );
+        assert!(dispatch_all(&mut runner).is_empty());
+        assert!(runner.progress().finished);
+        let report = runner.take_report(false).expect("The scenario is over");
+        assert!(!report.completed);
+        assert!(!report.is_success());
+        assert!(report.error.is_some_and(|error| error.contains("too many steps")));
+    }
+
+    #[test]
+    fn wait_for_ignores_responses_matched_to_a_request() {
+        let mut runner = runner(
+            r#"{ "to": 1, "request": { "TextRequest": "TextList" }, "save_as": "list" },
+               { "wait_for": { "from": 1 }, "save_as": "pushed" }"#,
+        );
+        let sessions = SessionTable::default();
+        let mut router = router();
+        let limits = PipelineLimits::default();
+
+        let dispatch = runner
+            .next_dispatch(&sessions, &limits, &mut router)
+            .expect("The first step is a request");
+        runner.dispatched(dispatch.instruction, dispatch.destination, 7, true);
+        runner.on_response(Some(7), 1, &text_list());
+
+        assert!(runner.next_dispatch(&sessions, &limits, &mut router).is_none());
+        assert_eq!(runner.progress().step, 1);
+        assert!(runner.variables.contains_key("list"));
+
+        runner.on_response(None, 1, &text_list());
+        assert!(runner.next_dispatch(&sessions, &limits, &mut router).is_none());
+        assert!(runner.progress().finished);
+        assert!(runner.variables.contains_key("pushed"));
+    }
+
+    #[test]
+    fn replaced_scenario_responses_do_not_satisfy_wait_for() {
+        let mut old = runner(r#"{ "to": 1, "request": { "TextRequest": "TextList" } }"#);
+        let sessions = SessionTable::default();
+        let mut router = router();
+        let limits = PipelineLimits::default();
+        let dispatch = old
+            .next_dispatch(&sessions, &limits, &mut router)
+            .expect("The first step is a request");
+        old.dispatched(dispatch.instruction, dispatch.destination, 7, true);
+
+        let mut new = runner(r#"{ "wait_for": { "from": 1 } }"#);
+        new.retire_sessions(old.unfinished_sessions());
+        new.on_response(Some(7), 1, &text_list());
+        assert!(new.unconsumed.is_empty());
+
+        new.on_response(Some(8), 1, &text_list());
+        assert_eq!(new.unconsumed.len(), 1);
+    }
+
+    #[test]
+    fn reports_the_steps_of_the_scenario_file() {
+        let mut runner = runner(
+            r#"{ "to": 1, "request": { "TextRequest": "TextList" }, "repeat": 2, "expect": { "kind": "TextList" } },
+               { "to": 1, "request": { "TextRequest": "TextList" }, "expect": { "kind": "TextList" } },
+               { "loop": { "times": 1, "steps": [
+                   { "to": 1, "request": { "TextRequest": "TextList" }, "expect": { "kind": "TextList" } }
+               ] } },
+               { "to": 1, "request": { "TextRequest": "TextList" }, "expect": { "kind": "TextList" } }"#,
+        );
+        dispatch_all(&mut runner);
+
+        let report = runner.take_report(false).expect("The scenario is over");
+        let steps: Vec<usize> = report.results.iter().map(|result| result.step).collect();
+        assert_eq!(steps, [0, 0, 1, 3, 4]);
+    }
+}
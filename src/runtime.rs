@@ -0,0 +1,520 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use ap_listener::{Command as ListenerCommand, Listener};
+use ap_sc_notifier::SimulationControllerNotifier;
+use ap_transmitter::{Command as TransmitterCommand, Transmitter};
+use crossbeam_channel::{at, never, select_biased, unbounded, Receiver, SendError, Sender};
+use messages::node_event::NodeEvent;
+use wg_2024::controller::DroneCommand;
+use wg_2024::network::NodeId;
+use wg_2024::packet::{NodeType, Packet};
+use crate::error::ClientError;
+use crate::event::ClientEvent;
+use crate::logic::LogicChannels;
+use crate::panic_hook::{self, PanicContext};
+
+/// A command sent to the logic of a node
+pub trait NodeCommand: Send + 'static {
+    /// The command asking the logic to quit
+    fn quit() -> Self;
+}
+
+/// The logic of a node run by a `NodeRuntime`, receiving commands of type `Command`.
+/// Every `ClientLogic` is a `NodeLogic` receiving `ClientCommand`s
+pub trait NodeLogic: Send {
+    type Command: NodeCommand;
+    /// The error the logic can end with. The runtime reports it as a `ClientError::Logic`,
+    /// unless it already is a `ClientError`
+    type Error: std::error::Error + Send + 'static;
+
+    fn node_id(&self) -> NodeId;
+
+    /// Runs the logic until it receives the quit command
+    /// # Errors
+    /// Returns an error if the logic cannot keep running
+    fn run_logic(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Where a node reports its `ClientEvent`s, shared by the runtime and the panic hook of its threads
+#[derive(Clone, Default)]
+pub(crate) struct EventSender {
+    event_tx: Arc<RwLock<Option<Sender<ClientEvent>>>>,
+}
+
+impl EventSender {
+    fn set(&self, event_tx: Sender<ClientEvent>) {
+        *self.event_tx.write().unwrap_or_else(PoisonError::into_inner) = Some(event_tx);
+    }
+
+    /// Sends `event`, if a channel has been set
+    /// # Errors
+    /// Returns the event back if the channel is disconnected
+    pub(crate) fn send(&self, event: ClientEvent) -> Result<(), SendError<ClientEvent>> {
+        match self.event_tx.read().unwrap_or_else(PoisonError::into_inner).as_ref() {
+            Some(event_tx) => event_tx.send(event),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Lifecycle of a node run by a `NodeRuntime`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeState {
+    /// The threads of the node are being started
+    Starting,
+    Running,
+    /// The threads of the node have been asked to quit
+    Stopping,
+    Stopped,
+    /// A thread could not be started, or ended with an error
+    Failed,
+}
+
+/// Handle to the `NodeState` of a node, which can be queried while the node runs
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    state: Arc<RwLock<NodeState>>,
+}
+
+impl NodeStatus {
+    fn new() -> Self {
+        Self {
+            state: Arc::new(RwLock::new(NodeState::Starting)),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self) -> NodeState {
+        *self.state.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn set(&self, state: NodeState) {
+        *self.state.write().unwrap_or_else(PoisonError::into_inner) = state;
+    }
+}
+
+/// The components of a node, each running on its own thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component {
+    Listener,
+    Transmitter,
+    Logic,
+}
+
+impl Display for Component {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Component::Listener => write!(f, "listener"),
+            Component::Transmitter => write!(f, "transmitter"),
+            Component::Logic => write!(f, "logic"),
+        }
+    }
+}
+
+/// How the logic thread of a node is restarted after a crash.
+/// The listener and the transmitter keep running in the meantime
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Maximum number of restarts during the life of the node. 0 disables the restarts
+    pub max_restarts: u32,
+    /// Time waited before restarting the logic
+    pub delay: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// A thread running a component of a node
+struct ComponentThread {
+    component: Component,
+    name: String,
+    handle: JoinHandle<Result<(), ClientError>>,
+}
+
+/// Notifies the runtime when the thread of a component ends, even by panicking
+struct ExitGuard {
+    component: Component,
+    exit_tx: Sender<Component>,
+}
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        let _ = self.exit_tx.send(self.component);
+    }
+}
+
+/// Owns the listener, the transmitter and the logic of a node, and runs each of them on its own thread
+pub struct NodeRuntime<L: NodeLogic + 'static> {
+    node_id: NodeId,
+    node_type: NodeType,
+    listener: Arc<Mutex<Listener>>,
+    listener_command_tx: Sender<ListenerCommand>,
+    logic: Arc<Mutex<L>>,
+    logic_command_tx: Sender<L::Command>,
+    transmitter: Arc<Mutex<Transmitter>>,
+    transmitter_command_tx: Sender<TransmitterCommand>,
+    threads: Vec<ComponentThread>,
+    exit_tx: Sender<Component>,
+    exit_rx: Receiver<Component>,
+    restart_policy: RestartPolicy,
+    restarts: u32,
+    /// When the crashed logic is to be restarted
+    restart_at: Option<Instant>,
+    status: NodeStatus,
+    event_tx: EventSender,
+}
+
+impl<L: NodeLogic + 'static> NodeRuntime<L> {
+    /// Creates the components of the node `node_id`. The logic is built by `build_logic`, out of the
+    /// channels connecting it to the listener and to the transmitter
+    /// # Errors
+    /// Returns an error if the components do not agree on the node ID
+    pub fn new<F: FnOnce(LogicChannels<L::Command>) -> L>(
+        node_id: NodeId,
+        node_type: NodeType,
+        listener_rx: Receiver<Packet>,
+        drones_tx: HashMap<NodeId, Sender<Packet>>,
+        simulation_controller_tx: Sender<NodeEvent>,
+        drone_command_rx: Receiver<DroneCommand>,
+        build_logic: F,
+    ) -> Result<Self, ClientError> {
+        let (listener_to_transmitter_tx, listener_to_transmitter_rx) = unbounded();
+        let (listener_to_logic_tx, listener_to_logic_rx) = unbounded();
+        let (logic_to_transmitter_tx, logic_to_transmitter_rx) = unbounded();
+        let (listener_command_tx, listener_command_rx) = unbounded();
+        let (transmitter_command_tx, transmitter_command_rx) = unbounded();
+        let (logic_command_tx, logic_command_rx) = unbounded();
+
+        let simulation_controller_notifier =
+            Arc::new(SimulationControllerNotifier::new(simulation_controller_tx));
+
+        let transmitter = Transmitter::new(
+            node_id,
+            node_type,
+            listener_to_transmitter_rx,
+            logic_to_transmitter_rx,
+            drones_tx,
+            simulation_controller_notifier.clone(),
+            transmitter_command_rx,
+            Duration::from_secs(60),
+            drone_command_rx,
+        );
+
+        let listener = Listener::new(
+            node_id,
+            listener_to_transmitter_tx,
+            listener_to_logic_tx,
+            listener_rx,
+            listener_command_rx,
+            simulation_controller_notifier,
+        );
+
+        let logic = build_logic(LogicChannels {
+            node_id,
+            transmitter_tx: logic_to_transmitter_tx,
+            listener_rx: listener_to_logic_rx,
+            command_rx: logic_command_rx,
+        });
+
+        for found in [transmitter.get_node_id(), listener.get_node_id(), logic.node_id()] {
+            if found != node_id {
+                return Err(ClientError::NodeIdMismatch { expected: node_id, found });
+            }
+        }
+
+        let (exit_tx, exit_rx) = unbounded();
+
+        Ok(Self {
+            node_id,
+            node_type,
+            listener: Arc::new(Mutex::new(listener)),
+            listener_command_tx,
+            logic: Arc::new(Mutex::new(logic)),
+            logic_command_tx,
+            transmitter: Arc::new(Mutex::new(transmitter)),
+            transmitter_command_tx,
+            threads: Vec::new(),
+            exit_tx,
+            exit_rx,
+            restart_policy: RestartPolicy::default(),
+            restarts: 0,
+            restart_at: None,
+            status: NodeStatus::new(),
+            event_tx: EventSender::default(),
+        })
+    }
+
+    #[must_use]
+    pub fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    /// Returns a handle to the lifecycle state of the node
+    #[must_use]
+    pub fn status(&self) -> NodeStatus {
+        self.status.clone()
+    }
+
+    /// Reports the errors, the panics and the state changes of the node on `event_tx`,
+    /// replacing the channel set before, also for the threads already running
+    pub fn set_event_tx(&mut self, event_tx: Sender<ClientEvent>) {
+        self.event_tx.set(event_tx);
+    }
+
+    /// Sets how the logic thread is restarted after a crash
+    pub fn set_restart_policy(&mut self, restart_policy: RestartPolicy) {
+        self.restart_policy = restart_policy;
+    }
+
+    /// Forwards `command` to the logic
+    /// # Errors
+    /// Returns an error if the logic thread is no longer running
+    pub fn send_to_logic(&self, command: L::Command) -> Result<(), ClientError> {
+        self.logic_command_tx
+            .send(command)
+            .map_err(|_| ClientError::Disconnected("logic"))
+    }
+
+    /// Starts the threads of the node
+    /// # Errors
+    /// Returns an error if a thread cannot be spawned, after stopping the ones already started
+    pub fn start(&mut self) -> Result<(), ClientError> {
+        panic_hook::install_hook();
+        self.set_state(NodeState::Starting);
+        match self.spawn_threads() {
+            Ok(()) => {
+                self.set_state(NodeState::Running);
+                Ok(())
+            }
+            Err(error) => {
+                self.report_error(error.clone());
+                let _ = self.shutdown();
+                self.set_state(NodeState::Failed);
+                Err(error)
+            }
+        }
+    }
+
+    /// Asks every thread of the node to quit and waits for them
+    /// # Errors
+    /// Returns the first error a thread ended with
+    pub fn stop(&mut self) -> Result<(), ClientError> {
+        let failed = self.status.get() == NodeState::Failed;
+        self.set_state(NodeState::Stopping);
+        let result = self.shutdown();
+        self.set_state(if result.is_ok() && !failed { NodeState::Stopped } else { NodeState::Failed });
+        result
+    }
+
+    /// Waits for the next command on `command_rx`, supervising the threads of the node in the meantime.
+    /// A crashed logic thread is restarted following the `RestartPolicy`; the commands received
+    /// in the meantime wait for it on its channel
+    /// # Errors
+    /// Returns an error if `command_rx` is disconnected, or if a thread ended and cannot be restarted
+    pub fn next_command<T>(&mut self, command_rx: &Receiver<T>) -> Result<T, ClientError> {
+        let exit_rx = self.exit_rx.clone();
+        loop {
+            let restart = self.restart_at.map_or_else(never, at);
+            select_biased! {
+                recv(exit_rx) -> component => {
+                    if let Ok(component) = component {
+                        self.supervise(component)?;
+                    }
+                },
+                recv(restart) -> _ => self.restart_logic()?,
+                recv(command_rx) -> command => {
+                    return command.map_err(|_| ClientError::Disconnected("command"));
+                },
+            }
+        }
+    }
+
+    /// Logs `error` and reports it as a `ClientEvent::Error`
+    pub(crate) fn report_error(&self, error: ClientError) {
+        log::error!("Node {}: {error}", self.node_id);
+        self.emit_event(ClientEvent::Error(error));
+    }
+
+    fn spawn_threads(&mut self) -> Result<(), ClientError> {
+        let listener = self.spawn(Component::Listener, self.listener.clone(), |listener| {
+            listener.run();
+            Ok(())
+        })?;
+        self.threads.push(listener);
+
+        let transmitter = self.spawn(Component::Transmitter, self.transmitter.clone(), |transmitter| {
+            transmitter.run();
+            Ok(())
+        })?;
+        self.threads.push(transmitter);
+
+        let logic = self.spawn(Component::Logic, self.logic.clone(), run_logic)?;
+        self.threads.push(logic);
+        Ok(())
+    }
+
+    /// Spawns the thread of `component`, which runs `target` once it acquires its lock
+    fn spawn<T, F>(&self, component: Component, target: Arc<Mutex<T>>, run: F) -> Result<ComponentThread, ClientError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut T) -> Result<(), ClientError> + Send + 'static,
+    {
+        let name = self.thread_name(component);
+        let thread_name = name.clone();
+        let exit_guard = ExitGuard {
+            component,
+            exit_tx: self.exit_tx.clone(),
+        };
+        let panic_context = PanicContext {
+            node_id: self.node_id,
+            component,
+            event_tx: self.event_tx.clone(),
+        };
+        let handle = thread::Builder::new()
+            .name(name.clone())
+            .spawn(move || {
+                let _exit_guard = exit_guard;
+                panic_hook::set_context(panic_context);
+                let mut target = target
+                    .lock()
+                    .map_err(|_| ClientError::LockPoisoned(thread_name))?;
+                run(&mut target)
+            })
+            .map_err(|error| ClientError::Spawn {
+                thread: name.clone(),
+                reason: error.to_string(),
+            })?;
+        Ok(ComponentThread { component, name, handle })
+    }
+
+    /// Handles the end of the thread of `component`, which had not been asked to quit.
+    /// The crash is reported, then the restart of the logic is scheduled if the `RestartPolicy` allows it
+    fn supervise(&mut self, component: Component) -> Result<(), ClientError> {
+        let Some(index) = self.threads.iter().position(|thread| thread.component == component) else {
+            return Ok(());
+        };
+        let ComponentThread { name, handle, .. } = self.threads.swap_remove(index);
+        let error = match handle.join() {
+            Ok(Ok(())) => ClientError::Stopped(name),
+            Ok(Err(error)) => error,
+            Err(_) => ClientError::ThreadPanicked(name),
+        };
+
+        let restarted = component == Component::Logic && self.restarts < self.restart_policy.max_restarts;
+        if restarted {
+            log::error!(
+                "Node {}: the {component} crashed: {error}. Restarting it in {:?}",
+                self.node_id,
+                self.restart_policy.delay
+            );
+        } else {
+            log::error!("Node {}: the {component} crashed: {error}. Not restarting it", self.node_id);
+        }
+        self.emit_event(ClientEvent::ComponentCrashed {
+            component,
+            error: error.clone(),
+            restarted,
+        });
+        if !restarted {
+            self.set_state(NodeState::Failed);
+            return Err(error);
+        }
+
+        self.restarts += 1;
+        self.restart_at = Some(Instant::now() + self.restart_policy.delay);
+        Ok(())
+    }
+
+    /// Restarts the crashed logic, once the delay of the `RestartPolicy` has elapsed
+    fn restart_logic(&mut self) -> Result<(), ClientError> {
+        self.restart_at = None;
+        // The state of the logic is kept: a panic only leaves its lock poisoned
+        self.logic.clear_poison();
+        match self.spawn(Component::Logic, self.logic.clone(), run_logic) {
+            Ok(logic) => {
+                log::info!(
+                    "Node {}: logic restarted ({}/{})",
+                    self.node_id,
+                    self.restarts,
+                    self.restart_policy.max_restarts
+                );
+                self.threads.push(logic);
+                Ok(())
+            }
+            Err(error) => {
+                self.set_state(NodeState::Failed);
+                Err(error)
+            }
+        }
+    }
+
+    /// Asks every component to quit and joins the started threads.
+    /// A component that cannot be reached has already stopped
+    fn shutdown(&mut self) -> Result<(), ClientError> {
+        self.restart_at = None;
+        if self.listener_command_tx.send(ListenerCommand::Quit).is_err() {
+            log::warn!("Node {}: the listener already stopped", self.node_id);
+        }
+        if self.logic_command_tx.send(L::Command::quit()).is_err() {
+            log::warn!("Node {}: the logic already stopped", self.node_id);
+        }
+        if self.transmitter_command_tx.send(TransmitterCommand::Quit).is_err() {
+            log::warn!("Node {}: the transmitter already stopped", self.node_id);
+        }
+
+        let mut result = Ok(());
+        for ComponentThread { name, handle, .. } in std::mem::take(&mut self.threads) {
+            let error = match handle.join() {
+                Ok(Ok(())) => continue,
+                Ok(Err(error)) => error,
+                Err(_) => ClientError::ThreadPanicked(name),
+            };
+            self.report_error(error.clone());
+            if result.is_ok() {
+                result = Err(error);
+            }
+        }
+        result
+    }
+
+    fn set_state(&self, state: NodeState) {
+        log::info!("Node {}: {state:?}", self.node_id);
+        self.status.set(state);
+        self.emit_event(ClientEvent::NodeStateChanged(state));
+    }
+
+    fn emit_event(&self, event: ClientEvent) {
+        if let Err(error) = self.event_tx.send(event) {
+            log::warn!("Cannot report node event. Error: {error:?}");
+        }
+    }
+
+    fn thread_name(&self, component: Component) -> String {
+        let node_type = match self.node_type {
+            NodeType::Client => "client",
+            NodeType::Drone => "drone",
+            NodeType::Server => "server",
+        };
+        format!("{node_type}_{}_{component}", self.node_id)
+    }
+}
+
+/// Runs `logic`, converting the error it ends with into a `ClientError`
+fn run_logic<L: NodeLogic>(logic: &mut L) -> Result<(), ClientError> {
+    logic.run_logic().map_err(|error| {
+        let error: Box<dyn std::error::Error + Send> = Box::new(error);
+        match error.downcast::<ClientError>() {
+            Ok(error) => *error,
+            Err(error) => ClientError::Logic(error.to_string()),
+        }
+    })
+}
@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::Duration;
+use messages::{ChatResponse, RequestType, ResponseType, ServerType};
+use regex::Regex;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use wg_2024::network::NodeId;
+use crate::report::Expectation;
+use crate::response::{Captured, ResponseKind};
+
+/// Placeholder referring to a saved response, such as `${list}`, `${list[0]}` or `${msg.from}`
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(\[\d+]|\.[a-z]+)?}")
+        .unwrap_or_else(|error| panic!("Invalid placeholder regex: {error}"))
+});
+
+/// The list of steps performed by a client, compiled into a flat program
+#[derive(Debug, Clone, Default)]
+pub struct Scenario {
+    pub(crate) program: Vec<Instruction>,
+    /// Index of the step of the scenario file each instruction comes from
+    pub(crate) steps: Vec<usize>,
+    pub(crate) counters: usize,
+}
+
+/// Error returned while loading a `Scenario`
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    /// A step refers to a destination name that is not declared in `destinations`
+    UnknownDestination(String),
+    /// A `wait_for` expects a response from a server type instead of a node
+    TypedSource,
+    /// A request without placeholders is not a valid `RequestType`
+    InvalidRequest(serde_json::Error),
+}
+
+impl Display for ScenarioError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScenarioError::Io(error) => write!(f, "Cannot read scenario: {error}"),
+            ScenarioError::Parse(error) => write!(f, "Cannot parse scenario: {error}"),
+            ScenarioError::UnknownDestination(name) => {
+                write!(f, "Unknown destination '{name}'")
+            }
+            ScenarioError::TypedSource => {
+                write!(f, "The source of a response must be a node, not a server type")
+            }
+            ScenarioError::InvalidRequest(error) => write!(f, "Invalid request: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(error: std::io::Error) -> Self {
+        ScenarioError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for ScenarioError {
+    fn from(error: serde_json::Error) -> Self {
+        ScenarioError::Parse(error)
+    }
+}
+
+/// Condition evaluated on the responses saved by the scenario
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Condition {
+    /// The saved response exists and has at least one entry or some content
+    NonEmpty(String),
+    /// The saved response does not exist or has no entries and no content
+    Empty(String),
+    /// The saved response exists and is of the given kind
+    Is { var: String, kind: ResponseKind },
+    Not(Box<Condition>),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+impl Condition {
+    pub(crate) fn evaluate(&self, variables: &HashMap<String, Captured>) -> bool {
+        match self {
+            Condition::NonEmpty(var) => variables
+                .get(var)
+                .is_some_and(|captured| !captured.is_empty()),
+            Condition::Empty(var) => !Condition::NonEmpty(var.clone()).evaluate(variables),
+            Condition::Is { var, kind } => variables
+                .get(var)
+                .is_some_and(|captured| captured.kind() == *kind),
+            Condition::Not(condition) => !condition.evaluate(variables),
+            Condition::All(conditions) => conditions
+                .iter()
+                .all(|condition| condition.evaluate(variables)),
+            Condition::Any(conditions) => conditions
+                .iter()
+                .any(|condition| condition.evaluate(variables)),
+        }
+    }
+}
+
+/// Describes the responses a `wait_for` step is waiting for. Unset fields match anything
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EventPattern {
+    pub kind: Option<ResponseKind>,
+    /// Node that sent the response
+    pub from: Option<NodeId>,
+    /// Client that sent a `MessageFrom`
+    pub sender: Option<NodeId>,
+}
+
+impl EventPattern {
+    pub(crate) fn matches(&self, captured: &Captured) -> bool {
+        let sender_matches = match (&self.sender, &captured.response) {
+            (None, _) => true,
+            (
+                Some(sender),
+                ResponseType::ChatResponse(ChatResponse::MessageFrom { from, .. }),
+            ) => sender == from,
+            (Some(_), _) => false,
+        };
+
+        sender_matches
+            && self.kind.is_none_or(|kind| kind == captured.kind())
+            && self.from.is_none_or(|from| from == captured.source)
+    }
+}
+
+/// A request that may contain placeholders, resolved just before it is sent
+#[derive(Debug, Clone)]
+pub(crate) enum RequestTemplate {
+    Ready(RequestType),
+    Template(Value),
+}
+
+impl RequestTemplate {
+    fn new(value: Value) -> Result<Self, ScenarioError> {
+        if Self::has_placeholders(&value) {
+            Ok(RequestTemplate::Template(value))
+        } else {
+            serde_json::from_value(value)
+                .map(RequestTemplate::Ready)
+                .map_err(ScenarioError::InvalidRequest)
+        }
+    }
+
+    /// Builds the `RequestType`, replacing the placeholders with the saved responses.
+    /// A string made of a single placeholder whose value is a number is replaced with that number
+    pub(crate) fn resolve(
+        &self,
+        variables: &HashMap<String, Captured>,
+    ) -> Result<RequestType, String> {
+        match self {
+            RequestTemplate::Ready(request) => Ok(request.clone()),
+            RequestTemplate::Template(value) => {
+                let value = Self::substitute(value, variables)?;
+                serde_json::from_value(value).map_err(|error| error.to_string())
+            }
+        }
+    }
+
+    fn has_placeholders(value: &Value) -> bool {
+        match value {
+            Value::String(string) => PLACEHOLDER.is_match(string),
+            Value::Array(values) => values.iter().any(Self::has_placeholders),
+            Value::Object(map) => map.values().any(Self::has_placeholders),
+            _ => false,
+        }
+    }
+
+    fn substitute(value: &Value, variables: &HashMap<String, Captured>) -> Result<Value, String> {
+        match value {
+            Value::String(string) => {
+                let mut missing = None;
+                let replaced = PLACEHOLDER.replace_all(string, |captures: &regex::Captures| {
+                    let accessor = captures.get(2).map_or("", |accessor| accessor.as_str());
+                    let value = variables
+                        .get(&captures[1])
+                        .and_then(|captured| captured.get(accessor));
+                    if value.is_none() {
+                        missing = Some(captures[0].to_string());
+                    }
+                    value.unwrap_or_default()
+                });
+
+                if let Some(placeholder) = missing {
+                    return Err(format!("Cannot resolve placeholder '{placeholder}'"));
+                }
+
+                let whole_placeholder = PLACEHOLDER
+                    .find(string)
+                    .is_some_and(|found| found.len() == string.len());
+                match replaced.parse::<u64>() {
+                    Ok(number) if whole_placeholder => Ok(Value::from(number)),
+                    _ => Ok(Value::String(replaced.into_owned())),
+                }
+            }
+            Value::Array(values) => values
+                .iter()
+                .map(|value| Self::substitute(value, variables))
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            Value::Object(map) => map
+                .iter()
+                .map(|(key, value)| Ok((key.clone(), Self::substitute(value, variables)?)))
+                .collect::<Result<serde_json::Map<_, _>, String>>()
+                .map(Value::Object),
+            _ => Ok(value.clone()),
+        }
+    }
+}
+
+/// Where a scenario request is sent
+#[derive(Debug, Clone)]
+pub(crate) enum Target {
+    Node(NodeId),
+    /// Any known server of this type, chosen with the `RoutingStrategy` of the client
+    AnyOf(ServerType),
+}
+
+/// Instructions of a compiled `Scenario`. Jump targets are indexes in the program
+#[derive(Debug, Clone)]
+pub(crate) enum Instruction {
+    /// Sends a request, then waits `delay` before the next `Send`
+    Send {
+        destination: Target,
+        request: RequestTemplate,
+        delay: Duration,
+        save_as: Option<String>,
+        expect: Option<Expectation>,
+    },
+    /// Waits for a matching response, giving up after `timeout` if set
+    WaitFor {
+        pattern: EventPattern,
+        timeout: Option<Duration>,
+        save_as: Option<String>,
+        expect: Option<Expectation>,
+    },
+    /// Jumps to `target` if `condition` is false. Waits for the requests in flight before evaluating
+    JumpUnless { condition: Condition, target: usize },
+    Jump { target: usize },
+    SetCounter { counter: usize, value: u32 },
+    /// Jumps to `target` if the counter is zero, otherwise decrements it
+    CountDown { counter: usize, target: usize },
+}
+
+/// Destination declared in a scenario file: either a `NodeId` or any server of a type, as `{ "any": "Text" }`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TargetFile {
+    Id(NodeId),
+    Any { any: ServerType },
+}
+
+impl From<TargetFile> for Target {
+    fn from(target: TargetFile) -> Self {
+        match target {
+            TargetFile::Id(node_id) => Target::Node(node_id),
+            TargetFile::Any { any } => Target::AnyOf(any),
+        }
+    }
+}
+
+/// Destination of a step in a scenario file: either a target or a declared name
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum DestinationFile {
+    Target(TargetFile),
+    Name(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SendFile {
+    to: DestinationFile,
+    request: Value,
+    delay_ms: Option<u64>,
+    #[serde(default = "SendFile::default_repeat")]
+    repeat: u32,
+    save_as: Option<String>,
+    expect: Option<Expectation>,
+}
+
+impl SendFile {
+    fn default_repeat() -> u32 {
+        1
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PatternFile {
+    kind: Option<ResponseKind>,
+    from: Option<DestinationFile>,
+    sender: Option<NodeId>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WaitFile {
+    wait_for: PatternFile,
+    timeout_ms: Option<u64>,
+    save_as: Option<String>,
+    expect: Option<Expectation>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoopBodyFile {
+    times: Option<u32>,
+    #[serde(rename = "while")]
+    condition: Option<Condition>,
+    steps: Vec<StepFile>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoopFile {
+    #[serde(rename = "loop")]
+    body: LoopBodyFile,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IfFile {
+    #[serde(rename = "if")]
+    condition: Condition,
+    then: Vec<StepFile>,
+    #[serde(default, rename = "else")]
+    otherwise: Vec<StepFile>,
+}
+
+/// A step of a scenario file, recognized by its distinctive key
+#[derive(Debug)]
+enum StepFile {
+    Send(SendFile),
+    WaitFor(WaitFile),
+    Loop(LoopFile),
+    If(IfFile),
+}
+
+impl<'de> Deserialize<'de> for StepFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        let has_key = |key: &str| value.get(key).is_some();
+
+        let step = if has_key("request") {
+            serde_json::from_value(value).map(StepFile::Send)
+        } else if has_key("wait_for") {
+            serde_json::from_value(value).map(StepFile::WaitFor)
+        } else if has_key("loop") {
+            serde_json::from_value(value).map(StepFile::Loop)
+        } else if has_key("if") {
+            serde_json::from_value(value).map(StepFile::If)
+        } else {
+            return Err(D::Error::custom(
+                "a step needs one of the keys 'request', 'wait_for', 'loop' or 'if'",
+            ));
+        };
+
+        step.map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScenarioFile {
+    #[serde(default)]
+    destinations: HashMap<String, TargetFile>,
+    #[serde(default)]
+    default_delay_ms: u64,
+    steps: Vec<StepFile>,
+}
+
+/// Translates the steps of a `ScenarioFile` into a `Scenario`
+struct Compiler<'a> {
+    destinations: &'a HashMap<String, TargetFile>,
+    default_delay: Duration,
+    scenario: Scenario,
+    /// Index of the step being compiled, counting the nested steps in order of appearance
+    step: usize,
+    next_step: usize,
+}
+
+impl Compiler<'_> {
+    fn destination(&self, destination: DestinationFile) -> Result<Target, ScenarioError> {
+        match destination {
+            DestinationFile::Target(target) => Ok(target.into()),
+            DestinationFile::Name(name) => self
+                .destinations
+                .get(&name)
+                .cloned()
+                .map(Target::from)
+                .ok_or(ScenarioError::UnknownDestination(name)),
+        }
+    }
+
+    /// Resolves a destination that must be a single node
+    fn node(&self, destination: DestinationFile) -> Result<NodeId, ScenarioError> {
+        match self.destination(destination)? {
+            Target::Node(node_id) => Ok(node_id),
+            Target::AnyOf(_) => Err(ScenarioError::TypedSource),
+        }
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.scenario.program.push(instruction);
+        self.scenario.steps.push(self.step);
+        self.scenario.program.len() - 1
+    }
+
+    fn new_counter(&mut self) -> usize {
+        self.scenario.counters += 1;
+        self.scenario.counters - 1
+    }
+
+    /// Sets the target of the jump at `index` to the next instruction
+    fn patch(&mut self, index: usize) {
+        let next = self.scenario.program.len();
+        match &mut self.scenario.program[index] {
+            Instruction::JumpUnless { target, .. }
+            | Instruction::Jump { target }
+            | Instruction::CountDown { target, .. } => *target = next,
+            _ => {}
+        }
+    }
+
+    fn compile(&mut self, steps: Vec<StepFile>) -> Result<(), ScenarioError> {
+        for step in steps {
+            let index = self.next_step;
+            self.next_step += 1;
+            self.step = index;
+            match step {
+                StepFile::Send(send) => {
+                    let instruction = Instruction::Send {
+                        destination: self.destination(send.to)?,
+                        request: RequestTemplate::new(send.request)?,
+                        delay: send
+                            .delay_ms
+                            .map_or(self.default_delay, Duration::from_millis),
+                        save_as: send.save_as,
+                        expect: send.expect,
+                    };
+                    self.compile_loop(Some(send.repeat), None, |compiler| {
+                        compiler.emit(instruction);
+                        Ok(())
+                    })?;
+                }
+                StepFile::WaitFor(wait) => {
+                    let from = wait
+                        .wait_for
+                        .from
+                        .map(|from| self.node(from))
+                        .transpose()?;
+                    self.emit(Instruction::WaitFor {
+                        pattern: EventPattern {
+                            kind: wait.wait_for.kind,
+                            from,
+                            sender: wait.wait_for.sender,
+                        },
+                        timeout: wait.timeout_ms.map(Duration::from_millis),
+                        save_as: wait.save_as,
+                        expect: wait.expect,
+                    });
+                }
+                StepFile::Loop(LoopFile { body }) => {
+                    let steps = body.steps;
+                    self.compile_loop(body.times, body.condition, |compiler| {
+                        let compiled = compiler.compile(steps);
+                        compiler.step = index;
+                        compiled
+                    })?;
+                }
+                StepFile::If(branch) => {
+                    let jump_unless = self.emit(Instruction::JumpUnless {
+                        condition: branch.condition,
+                        target: 0,
+                    });
+                    self.compile(branch.then)?;
+                    self.step = index;
+                    if branch.otherwise.is_empty() {
+                        self.patch(jump_unless);
+                    } else {
+                        let jump = self.emit(Instruction::Jump { target: 0 });
+                        self.patch(jump_unless);
+                        self.compile(branch.otherwise)?;
+                        self.patch(jump);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits `body` repeated `times` times and while `condition` holds.
+    /// Without a count or a condition the body is repeated forever
+    fn compile_loop<F>(
+        &mut self,
+        times: Option<u32>,
+        condition: Option<Condition>,
+        body: F,
+    ) -> Result<(), ScenarioError>
+    where
+        F: FnOnce(&mut Self) -> Result<(), ScenarioError>,
+    {
+        if times == Some(1) && condition.is_none() {
+            return body(self);
+        }
+
+        let count_down = times.map(|value| {
+            let counter = self.new_counter();
+            self.emit(Instruction::SetCounter { counter, value });
+            self.emit(Instruction::CountDown { counter, target: 0 })
+        });
+        let start = count_down.unwrap_or(self.scenario.program.len());
+        let jump_unless = condition
+            .map(|condition| self.emit(Instruction::JumpUnless { condition, target: 0 }));
+
+        body(self)?;
+        self.emit(Instruction::Jump { target: start });
+
+        for exit in count_down.into_iter().chain(jump_unless) {
+            self.patch(exit);
+        }
+        Ok(())
+    }
+}
+
+impl Scenario {
+    /// Returns the index of the step of the scenario file `instruction` comes from
+    pub(crate) fn step_of(&self, instruction: usize) -> usize {
+        self.steps.get(instruction).copied().unwrap_or(instruction)
+    }
+
+    /// Creates a scenario sending `actions` in order, waiting `delay` after each of them
+    #[must_use]
+    pub fn from_actions(actions: Vec<(NodeId, RequestType)>, delay: Duration) -> Self {
+        let program: Vec<Instruction> = actions
+            .into_iter()
+            .map(|(destination, request)| Instruction::Send {
+                destination: Target::Node(destination),
+                request: RequestTemplate::Ready(request),
+                delay,
+                save_as: None,
+                expect: None,
+            })
+            .collect();
+        Self {
+            steps: (0..program.len()).collect(),
+            program,
+            counters: 0,
+        }
+    }
+
+    /// Parses a scenario written in JSON, such as
+    /// ```json
+    /// {
+    ///     "destinations": { "text": 10, "chat": 11, "media": { "any": "Media" } },
+    ///     "default_delay_ms": 100,
+    ///     "steps": [
+    ///         { "to": "text", "request": { "TextRequest": "TextList" }, "save_as": "list",
+    ///           "expect": { "kind": "TextList", "min_len": 1, "within_ms": 2000 } },
+    ///         { "if": { "non_empty": "list" },
+    ///           "then": [ { "to": "text", "request": { "TextRequest": { "Text": "${list[0]}" } } } ] },
+    ///         { "to": "chat", "request": { "ChatRequest": "Register" }, "delay_ms": 500 },
+    ///         { "wait_for": { "kind": "MessageFrom", "from": "chat", "sender": 5 },
+    ///           "timeout_ms": 10000, "save_as": "msg" },
+    ///         { "loop": { "times": 3, "steps": [
+    ///             { "to": "media", "request": { "MediaRequest": { "Media": "logo.png" } } }
+    ///         ] } }
+    ///     ]
+    /// }
+    /// ```
+    /// Destinations are node IDs, or `{ "any": <ServerType> }` to let the client choose among the discovered
+    /// servers of that type following its `RoutingStrategy`. Responses can be awaited from nodes only.
+    ///
+    /// Each step is one of:
+    /// - a request, with `to` and `request`, a `RequestType` in its serialized form.
+    ///   `delay_ms` overrides `default_delay_ms`, `repeat` sends it multiple times and
+    ///   `save_as` stores its response
+    /// - `wait_for` a response matching `kind`, `from` and `sender` (for `MessageFrom`),
+    ///   optionally giving up after `timeout_ms`
+    /// - `loop` over `steps`, `times` times and/or `while` a condition holds
+    /// - `if` a condition holds run `then`, otherwise `else`
+    ///
+    /// Requests and waits can declare what they `expect` to receive: a `kind`, whether it is
+    /// `not_found`, a `len`, `min_len` or `max_len` and a maximum latency `within_ms`.
+    /// The outcomes are collected in a `ScenarioReport`.
+    ///
+    /// Conditions are `non_empty`, `empty`, `is` (`{ "var": ..., "kind": ... }`), `not`, `all` and `any`.
+    /// Strings in requests can refer to saved responses with `${name}`, `${name[i]}`, `${name.len}`,
+    /// `${name.source}` and `${name.from}`
+    /// # Errors
+    /// Returns an error if the JSON is malformed or a step refers to an undeclared destination
+    pub fn from_json(json: &str) -> Result<Self, ScenarioError> {
+        let file: ScenarioFile = serde_json::from_str(json)?;
+
+        let mut compiler = Compiler {
+            destinations: &file.destinations,
+            default_delay: Duration::from_millis(file.default_delay_ms),
+            scenario: Scenario::default(),
+            step: 0,
+            next_step: 0,
+        };
+        compiler.compile(file.steps)?;
+        Ok(compiler.scenario)
+    }
+
+    /// Reads and parses the JSON scenario file at `path`. See `Scenario::from_json` for the format
+    /// # Errors
+    /// Returns an error if the file cannot be read or parsed
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ScenarioError> {
+        let json = std::fs::read_to_string(path)?;
+        Self::from_json(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEND_TO_1: &str = r#"{ "to": 1, "request": { "TextRequest": "TextList" } }"#;
+    const SEND_TO_2: &str = r#"{ "to": 2, "request": { "TextRequest": "TextList" } }"#;
+
+    fn compile(steps: &str) -> Vec<Instruction> {
+        Scenario::from_json(&format!(r#"{{ "steps": [{steps}] }}"#))
+            .expect("The scenario is valid")
+            .program
+    }
+
+    #[test]
+    fn counted_loop_jumps_back_to_its_count_down() {
+        let program = compile(&format!(r#"{{ "loop": {{ "times": 2, "steps": [{SEND_TO_1}] }} }}"#));
+        assert_eq!(program.len(), 4);
+        assert!(matches!(program[0], Instruction::SetCounter { counter: 0, value: 2 }));
+        assert!(matches!(program[1], Instruction::CountDown { counter: 0, target: 4 }));
+        assert!(matches!(program[2], Instruction::Send { .. }));
+        assert!(matches!(program[3], Instruction::Jump { target: 1 }));
+    }
+
+    #[test]
+    fn conditional_loop_exits_past_its_body() {
+        let program = compile(&format!(
+            r#"{{ "loop": {{ "while": {{ "empty": "list" }}, "steps": [{SEND_TO_1}] }} }}"#
+        ));
+        assert_eq!(program.len(), 3);
+        assert!(matches!(program[0], Instruction::JumpUnless { target: 3, .. }));
+        assert!(matches!(program[2], Instruction::Jump { target: 0 }));
+    }
+
+    #[test]
+    fn nested_loops_use_their_own_counters() {
+        let program = compile(&format!(
+            r#"{{ "loop": {{ "times": 2, "steps": [{{ "loop": {{ "times": 3, "steps": [{SEND_TO_1}] }} }}] }} }}"#
+        ));
+        assert_eq!(program.len(), 7);
+        assert!(matches!(program[1], Instruction::CountDown { counter: 0, target: 7 }));
+        assert!(matches!(program[2], Instruction::SetCounter { counter: 1, value: 3 }));
+        assert!(matches!(program[3], Instruction::CountDown { counter: 1, target: 6 }));
+        assert!(matches!(program[5], Instruction::Jump { target: 3 }));
+        assert!(matches!(program[6], Instruction::Jump { target: 1 }));
+    }
+
+    #[test]
+    fn if_without_else_jumps_past_the_then_branch() {
+        let program = compile(&format!(
+            r#"{{ "if": {{ "non_empty": "list" }}, "then": [{SEND_TO_1}, {SEND_TO_1}] }}"#
+        ));
+        assert_eq!(program.len(), 3);
+        assert!(matches!(program[0], Instruction::JumpUnless { target: 3, .. }));
+    }
+
+    #[test]
+    fn if_with_else_jumps_over_the_other_branch() {
+        let program = compile(&format!(
+            r#"{{ "if": {{ "non_empty": "list" }}, "then": [{SEND_TO_1}], "else": [{SEND_TO_2}] }}"#
+        ));
+        assert_eq!(program.len(), 4);
+        assert!(matches!(program[0], Instruction::JumpUnless { target: 3, .. }));
+        assert!(matches!(program[2], Instruction::Jump { target: 4 }));
+    }
+
+    #[test]
+    fn single_send_is_not_wrapped_in_a_loop() {
+        let program = compile(SEND_TO_1);
+        assert_eq!(program.len(), 1);
+        assert!(matches!(program[0], Instruction::Send { .. }));
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Instant;
+use messages::RequestType;
+use wg_2024::network::NodeId;
+
+/// Number of completed sessions remembered to detect duplicated responses
+const COMPLETED_HISTORY: usize = 1024;
+
+/// State of a request in the `SessionTable`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PendingState {
+    /// The request has been sent and the client is waiting for the response until `deadline`
+    AwaitingResponse,
+    /// The last attempt timed out and the request will be sent again at `deadline`
+    BackingOff,
+}
+
+/// A request that has been sent and is still waiting for its response
+#[derive(Debug, Clone)]
+pub struct PendingRequest {
+    pub destination: NodeId,
+    pub request: RequestType,
+    /// When the first attempt was sent
+    pub sent_at: Instant,
+    /// When the current attempt was sent
+    pub attempt_sent_at: Instant,
+    /// Number of attempts sent so far, starting from 1
+    pub attempt: u32,
+    pub state: PendingState,
+    pub deadline: Instant,
+    /// Sessions used by the previous attempts of this request
+    pub previous_sessions: Vec<u64>,
+    /// Whether the client registered again to the chat server after it rejected this request
+    pub reregistered: bool,
+}
+
+impl PendingRequest {
+    #[must_use]
+    pub fn new(destination: NodeId, request: RequestType, deadline: Instant) -> Self {
+        let now = Instant::now();
+        Self {
+            destination,
+            request,
+            sent_at: now,
+            attempt_sent_at: now,
+            attempt: 1,
+            state: PendingState::AwaitingResponse,
+            deadline,
+            previous_sessions: Vec::new(),
+            reregistered: false,
+        }
+    }
+
+    /// Returns the session of the first attempt, given the `current_session_id`
+    #[must_use]
+    pub fn origin_session(&self, current_session_id: u64) -> u64 {
+        self.previous_sessions
+            .first()
+            .copied()
+            .unwrap_or(current_session_id)
+    }
+}
+
+/// Result of matching a received response against the `SessionTable`
+#[derive(Debug)]
+pub enum SessionMatch {
+    /// The response answers the contained pending request, whose current session is `session_id`
+    Matched {
+        session_id: u64,
+        pending: PendingRequest,
+    },
+    /// The session is known, but the response comes from a node different from the destination
+    WrongSource { expected: NodeId },
+    /// The session has already been answered
+    Duplicate,
+    /// The session has never been opened by this client
+    Unknown,
+}
+
+/// Table of the requests waiting for a response, keyed by `session_id`.
+/// A request that is sent again after a timeout gets a new session, but responses to
+/// any of its previous sessions are still matched with it
+#[derive(Debug, Default)]
+pub struct SessionTable {
+    pending: HashMap<u64, PendingRequest>,
+    superseded: HashMap<u64, u64>,
+    completed: HashSet<u64>,
+    completed_order: VecDeque<u64>,
+}
+
+impl SessionTable {
+    /// Returns `true` if `session_id` is neither pending nor recently completed
+    #[must_use]
+    pub fn is_free(&self, session_id: u64) -> bool {
+        !self.pending.contains_key(&session_id)
+            && !self.superseded.contains_key(&session_id)
+            && !self.completed.contains(&session_id)
+    }
+
+    /// Returns `true` if the request sent with `session_id` is still waiting for its response,
+    /// even if it has been sent again with another session in the meantime
+    #[must_use]
+    pub fn is_pending(&self, session_id: u64) -> bool {
+        self.pending.contains_key(&self.current_session(session_id))
+    }
+
+    /// Returns the number of requests waiting for a response
+    #[must_use]
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns `true` if some request waiting for a response satisfies `predicate`
+    pub fn any_pending<F: Fn(&PendingRequest) -> bool>(&self, predicate: F) -> bool {
+        self.pending.values().any(predicate)
+    }
+
+    /// Registers a sent request waiting for a response
+    pub fn insert(&mut self, session_id: u64, request: PendingRequest) {
+        if self.pending.insert(session_id, request).is_some() {
+            log::warn!("Session {session_id} was already pending and has been overwritten");
+        }
+    }
+
+    /// Registers `request`, previously pending with `old_session_id`, as sent again with `new_session_id`
+    pub fn supersede(&mut self, old_session_id: u64, new_session_id: u64, mut request: PendingRequest) {
+        request.previous_sessions.push(old_session_id);
+        for previous in &request.previous_sessions {
+            self.superseded.insert(*previous, new_session_id);
+        }
+        self.insert(new_session_id, request);
+    }
+
+    /// Returns the earliest deadline among the pending requests
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.deadline).min()
+    }
+
+    /// Removes and returns the requests whose deadline is not after `now`
+    pub fn take_expired(&mut self, now: Instant) -> Vec<(u64, PendingRequest)> {
+        let expired: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline <= now)
+            .map(|(session_id, _)| *session_id)
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|session_id| {
+                self.pending
+                    .remove(&session_id)
+                    .map(|pending| (session_id, pending))
+            })
+            .collect()
+    }
+
+    /// Marks a request taken out of the table as finished, so that late responses are flagged as duplicated
+    pub fn finish(&mut self, session_id: u64, request: &PendingRequest) {
+        self.complete(session_id);
+        for previous in &request.previous_sessions {
+            self.superseded.remove(previous);
+            self.complete(*previous);
+        }
+    }
+
+    /// Matches a response received from `source` with `session_id` against the pending requests.
+    /// A matched request is removed from the table and remembered as completed
+    pub fn resolve(&mut self, session_id: u64, source: NodeId) -> SessionMatch {
+        let current = self.current_session(session_id);
+
+        if let Some(pending) = self.pending.get(&current) {
+            if pending.destination != source {
+                return SessionMatch::WrongSource {
+                    expected: pending.destination,
+                };
+            }
+        }
+
+        match self.pending.remove(&current) {
+            Some(pending) => {
+                self.finish(current, &pending);
+                SessionMatch::Matched {
+                    session_id: current,
+                    pending,
+                }
+            }
+            None if self.completed.contains(&session_id) => SessionMatch::Duplicate,
+            None => SessionMatch::Unknown,
+        }
+    }
+
+    fn current_session(&self, session_id: u64) -> u64 {
+        self.superseded
+            .get(&session_id)
+            .copied()
+            .unwrap_or(session_id)
+    }
+
+    fn complete(&mut self, session_id: u64) {
+        if self.completed.insert(session_id) {
+            self.completed_order.push_back(session_id);
+        }
+        while self.completed_order.len() > COMPLETED_HISTORY {
+            if let Some(oldest) = self.completed_order.pop_front() {
+                self.completed.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use messages::TextRequest;
+    use super::*;
+
+    fn request(destination: NodeId, deadline: Instant) -> PendingRequest {
+        PendingRequest::new(destination, RequestType::TextRequest(TextRequest::TextList), deadline)
+    }
+
+    #[test]
+    fn matches_responses_with_their_session_and_source() {
+        let mut sessions = SessionTable::default();
+        sessions.insert(1, request(10, Instant::now()));
+
+        assert!(matches!(sessions.resolve(1, 11), SessionMatch::WrongSource { expected: 10 }));
+        assert!(matches!(sessions.resolve(2, 10), SessionMatch::Unknown));
+        assert!(matches!(sessions.resolve(1, 10), SessionMatch::Matched { session_id: 1, .. }));
+        assert_eq!(sessions.pending_count(), 0);
+    }
+
+    #[test]
+    fn late_response_to_a_superseded_session_resolves_to_the_retry() {
+        let mut sessions = SessionTable::default();
+        sessions.insert(1, request(10, Instant::now()));
+        let (_, pending) = sessions.take_expired(Instant::now()).remove(0);
+        sessions.supersede(1, 2, pending);
+
+        assert!(sessions.is_pending(1));
+        assert!(!sessions.is_free(1));
+        match sessions.resolve(1, 10) {
+            SessionMatch::Matched { session_id, pending } => {
+                assert_eq!(session_id, 2);
+                assert_eq!(pending.previous_sessions, [1]);
+                assert_eq!(pending.origin_session(session_id), 1);
+            }
+            other => panic!("expected a match, got {other:?}"),
+        }
+        assert!(matches!(sessions.resolve(2, 10), SessionMatch::Duplicate));
+        assert!(matches!(sessions.resolve(1, 10), SessionMatch::Duplicate));
+    }
+
+    #[test]
+    fn response_after_finish_is_a_duplicate() {
+        let mut sessions = SessionTable::default();
+        sessions.insert(1, request(10, Instant::now()));
+        let (_, pending) = sessions.take_expired(Instant::now()).remove(0);
+        sessions.supersede(1, 2, pending);
+        let (_, pending) = sessions.take_expired(Instant::now()).remove(0);
+        sessions.finish(2, &pending);
+
+        assert!(!sessions.is_pending(2));
+        assert!(matches!(sessions.resolve(2, 10), SessionMatch::Duplicate));
+        assert!(matches!(sessions.resolve(1, 10), SessionMatch::Duplicate));
+    }
+
+    #[test]
+    fn takes_only_the_expired_requests() {
+        let now = Instant::now();
+        let mut sessions = SessionTable::default();
+        sessions.insert(1, request(10, now));
+        sessions.insert(2, request(10, now + Duration::from_secs(60)));
+        assert_eq!(sessions.next_deadline(), Some(now));
+
+        let expired = sessions.take_expired(now);
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, 1);
+        assert!(sessions.is_pending(2));
+        assert_eq!(sessions.next_deadline(), Some(now + Duration::from_secs(60)));
+        assert!(sessions.take_expired(now).is_empty());
+    }
+}
@@ -0,0 +1,28 @@
+use std::time::Duration;
+use wg_2024::network::NodeId;
+
+/// Snapshot of a running client, answered to `Command::QueryState`
+#[derive(Debug, Clone)]
+pub struct ClientState {
+    pub node_id: NodeId,
+    pub scenario: ScenarioProgress,
+    /// Requests waiting for their response, including the ones sent outside of the scenario
+    pub pending_requests: usize,
+    /// `false` once the listener has been found disconnected
+    pub listener_connected: bool,
+}
+
+/// How far the running scenario has got
+#[derive(Debug, Clone)]
+pub struct ScenarioProgress {
+    /// Index of the next instruction to execute
+    pub step: usize,
+    /// Number of instructions of the scenario
+    pub steps: usize,
+    /// Scenario requests waiting for their response
+    pub in_flight: usize,
+    pub finished: bool,
+    pub paused: bool,
+    /// Delay set with `Command::SetSleepTime`, replacing the ones of the scenario
+    pub sleep_time: Option<Duration>,
+}
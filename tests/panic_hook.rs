@@ -0,0 +1,109 @@
+//! The panic hook is global to the process, so its test runs alone in this binary
+
+use std::collections::HashMap;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use ap_client::{ClientError, ClientEvent, Component, NodeCommand, NodeLogic, NodeRuntime, RestartPolicy};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use wg_2024::network::NodeId;
+use wg_2024::packet::NodeType;
+
+struct Quit;
+
+impl NodeCommand for Quit {
+    fn quit() -> Self {
+        Quit
+    }
+}
+
+/// Panics as soon as it runs
+struct PanickingLogic {
+    node_id: NodeId,
+}
+
+impl NodeLogic for PanickingLogic {
+    type Command = Quit;
+    type Error = ClientError;
+
+    fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    fn run_logic(&mut self) -> Result<(), ClientError> {
+        panic!("logic of node {} panicked", self.node_id);
+    }
+}
+
+fn start_node(node_id: NodeId, event_tx: Sender<ClientEvent>) -> (NodeRuntime<PanickingLogic>, Sender<()>) {
+    let (packet_tx, packet_rx) = unbounded();
+    let (drone_command_tx, drone_command_rx) = unbounded();
+    let mut runtime = NodeRuntime::new(
+        node_id,
+        NodeType::Client,
+        packet_rx,
+        HashMap::new(),
+        unbounded().0,
+        drone_command_rx,
+        |_| PanickingLogic { node_id },
+    )
+    .expect("The node is valid");
+    runtime.set_event_tx(event_tx);
+    runtime.set_restart_policy(RestartPolicy {
+        max_restarts: 0,
+        ..RestartPolicy::default()
+    });
+    // The senders are kept so that the listener and the transmitter keep running
+    let (keep_alive_tx, keep_alive_rx) = unbounded::<()>();
+    std::thread::spawn(move || {
+        let _senders = (packet_tx, drone_command_tx);
+        let _ = keep_alive_rx.recv();
+    });
+    runtime.start().expect("The node starts");
+    (runtime, keep_alive_tx)
+}
+
+fn panics(event_rx: &Receiver<ClientEvent>) -> Vec<(NodeId, Component, String)> {
+    event_rx
+        .try_iter()
+        .filter_map(|event| match event {
+            ClientEvent::Panicked {
+                node_id,
+                component,
+                message,
+                ..
+            } => Some((node_id, component, message)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn reports_logic_panics_and_calls_the_previous_hook_once() {
+    let previous_calls = Arc::new(AtomicUsize::new(0));
+    let calls = previous_calls.clone();
+    panic::set_hook(Box::new(move |_| {
+        calls.fetch_add(1, Ordering::SeqCst);
+    }));
+
+    let (first_tx, first_rx) = unbounded();
+    let (second_tx, second_rx) = unbounded();
+    let (mut first, _first_alive) = start_node(1, first_tx);
+    let (mut second, _second_alive) = start_node(2, second_tx);
+
+    let (_command_tx, command_rx) = unbounded::<()>();
+    assert!(matches!(first.next_command(&command_rx), Err(ClientError::ThreadPanicked(_))));
+    assert!(matches!(second.next_command(&command_rx), Err(ClientError::ThreadPanicked(_))));
+    let _ = first.stop();
+    let _ = second.stop();
+
+    assert_eq!(
+        panics(&first_rx),
+        [(1, Component::Logic, "logic of node 1 panicked".to_string())]
+    );
+    assert_eq!(
+        panics(&second_rx),
+        [(2, Component::Logic, "logic of node 2 panicked".to_string())]
+    );
+    assert_eq!(previous_calls.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,151 @@
+//! Restarts of a crashed logic by the `NodeRuntime`
+
+use std::collections::HashMap;
+use std::time::Duration;
+use ap_client::{
+    ClientError, ClientEvent, Component, LogicChannels, NodeCommand, NodeLogic, NodeRuntime, NodeState, RestartPolicy,
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use wg_2024::network::NodeId;
+use wg_2024::packet::NodeType;
+
+struct Quit;
+
+impl NodeCommand for Quit {
+    fn quit() -> Self {
+        Quit
+    }
+}
+
+/// Panics on its first `panics` runs, then waits to be asked to quit.
+/// Its state survives the restarts, like the one of a real logic
+struct FlakyLogic {
+    node_id: NodeId,
+    command_rx: Receiver<Quit>,
+    panics: u32,
+    runs: u32,
+    started_tx: Sender<u32>,
+}
+
+impl NodeLogic for FlakyLogic {
+    type Command = Quit;
+    type Error = ClientError;
+
+    fn node_id(&self) -> NodeId {
+        self.node_id
+    }
+
+    fn run_logic(&mut self) -> Result<(), ClientError> {
+        self.runs += 1;
+        let _ = self.started_tx.send(self.runs);
+        if self.runs <= self.panics {
+            panic!("run {} of the logic panicked", self.runs);
+        }
+        self.command_rx
+            .recv()
+            .map(|Quit| ())
+            .map_err(|_| ClientError::Disconnected("command"))
+    }
+}
+
+struct TestNode {
+    runtime: NodeRuntime<FlakyLogic>,
+    event_rx: Receiver<ClientEvent>,
+    started_rx: Receiver<u32>,
+    _keep_alive_tx: Sender<()>,
+}
+
+fn start_node(panics: u32, max_restarts: u32) -> TestNode {
+    let (packet_tx, packet_rx) = unbounded();
+    let (drone_command_tx, drone_command_rx) = unbounded();
+    let (event_tx, event_rx) = unbounded();
+    let (started_tx, started_rx) = unbounded();
+    let mut runtime = NodeRuntime::new(
+        1,
+        NodeType::Client,
+        packet_rx,
+        HashMap::new(),
+        unbounded().0,
+        drone_command_rx,
+        |channels: LogicChannels<Quit>| FlakyLogic {
+            node_id: channels.node_id,
+            command_rx: channels.command_rx,
+            panics,
+            runs: 0,
+            started_tx,
+        },
+    )
+    .expect("The node is valid");
+    runtime.set_event_tx(event_tx);
+    runtime.set_restart_policy(RestartPolicy {
+        max_restarts,
+        delay: Duration::from_millis(10),
+    });
+    // The senders are kept so that the listener and the transmitter keep running
+    let (keep_alive_tx, keep_alive_rx) = unbounded::<()>();
+    std::thread::spawn(move || {
+        let _senders = (packet_tx, drone_command_tx);
+        let _ = keep_alive_rx.recv();
+    });
+    runtime.start().expect("The node starts");
+    TestNode {
+        runtime,
+        event_rx,
+        started_rx,
+        _keep_alive_tx: keep_alive_tx,
+    }
+}
+
+fn crashes(event_rx: &Receiver<ClientEvent>) -> Vec<(Component, bool)> {
+    event_rx
+        .try_iter()
+        .filter_map(|event| match event {
+            ClientEvent::ComponentCrashed {
+                component, restarted, ..
+            } => Some((component, restarted)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn restarts_the_logic_and_keeps_the_other_components_running() {
+    let mut node = start_node(1, 1);
+    let (command_tx, command_rx) = unbounded();
+    let started_rx = node.started_rx.clone();
+    // The command is sent once the logic runs again, so the restart happens while waiting for it
+    std::thread::spawn(move || {
+        if started_rx.iter().nth(1).is_some() {
+            let _ = command_tx.send(());
+        }
+    });
+
+    assert!(node.runtime.next_command(&command_rx).is_ok());
+    assert_eq!(node.runtime.status().get(), NodeState::Running);
+    assert_eq!(crashes(&node.event_rx), [(Component::Logic, true)]);
+
+    // The listener and the transmitter are still up to be asked to quit, as is the restarted logic
+    assert!(node.runtime.stop().is_ok());
+    assert_eq!(node.runtime.status().get(), NodeState::Stopped);
+    assert!(crashes(&node.event_rx).is_empty());
+}
+
+#[test]
+fn fails_once_the_restarts_are_exhausted() {
+    let mut node = start_node(u32::MAX, 2);
+    let (_command_tx, command_rx) = unbounded::<()>();
+
+    assert!(matches!(
+        node.runtime.next_command(&command_rx),
+        Err(ClientError::ThreadPanicked(_))
+    ));
+    assert_eq!(node.runtime.status().get(), NodeState::Failed);
+    assert_eq!(node.started_rx.try_iter().count(), 3);
+    assert_eq!(
+        crashes(&node.event_rx),
+        [(Component::Logic, true), (Component::Logic, true), (Component::Logic, false)]
+    );
+
+    let _ = node.runtime.stop();
+    assert_eq!(node.runtime.status().get(), NodeState::Failed);
+}